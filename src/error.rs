@@ -9,6 +9,10 @@ pub enum ProcessingError {
     #[error("Invalid ticket data: {0}")]
     InvalidTicketData(String),
 
+    /// A configuration file, template or setting could not be loaded
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+
     #[error("Network error occurred: {0}")]
     NetworkError(String),
 
@@ -24,6 +28,18 @@ pub enum ProcessingError {
     #[error("Priority calculation failed: {0}")]
     PriorityCalculationError(String),
 
+    #[error("Routing failed: {0}")]
+    RoutingError(String),
+
+    #[error("Translation failed: {0}")]
+    TranslationError(String),
+
+    #[error("Summarization failed: {0}")]
+    SummarizationError(String),
+
+    #[error("Reply draft failed: {0}")]
+    ReplyDraftError(String),
+
     #[error("Unknown error occurred: {0}")]
     UnknownError(String),
 }
@@ -0,0 +1,531 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ProcessingError,
+    pipeline::TicketObserver,
+    processors::urgency::phrase_pattern,
+    ticket::{ProcessedTicket, ProcessingResult},
+};
+
+/// Source of the current time, so that rate based logic can be tested with a simulated clock
+pub trait Clock: Sync + Send {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// A group of tickets arriving much faster than usual
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IncidentAlert {
+    /// What the tickets have in common, e.g. `category=Technical keyword=login`
+    pub key: String,
+    /// Tickets with the key within the detection window
+    pub count: usize,
+    /// Tickets with the key expected within the detection window, from the baseline
+    pub expected: f32,
+    pub window_minutes: i64,
+    pub detected_at: DateTime<Utc>,
+    pub ticket_ids: Vec<String>,
+}
+
+/// Delivers incident alerts, e.g. to a chat channel or a paging service
+#[async_trait]
+pub trait IncidentNotifier: Sync + Send {
+    async fn notify(&self, alert: &IncidentAlert) -> Result<(), ProcessingError>;
+}
+
+/// Writes alerts to the log
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogNotifier;
+
+#[async_trait]
+impl IncidentNotifier for LogNotifier {
+    async fn notify(&self, alert: &IncidentAlert) -> Result<(), ProcessingError> {
+        warn!(
+            "Possible incident: {} tickets with {} in the last {} minutes, {:.1} expected",
+            alert.count, alert.key, alert.window_minutes, alert.expected
+        );
+        Ok(())
+    }
+}
+
+/// Posts alerts as JSON to a webhook
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl IncidentNotifier for WebhookNotifier {
+    async fn notify(&self, alert: &IncidentAlert) -> Result<(), ProcessingError> {
+        let response = self.client.post(&self.url).json(alert).send().await?;
+        if !response.status().is_success() {
+            return Err(ProcessingError::NetworkError(format!(
+                "Incident webhook returned {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpikeConfig {
+    /// Window in which a spike is detected, 15 minutes by default
+    pub window: Duration,
+    /// Window over which the normal rate is measured, 24 hours by default
+    pub baseline_window: Duration,
+    /// How many times the normal rate counts as a spike, 5 by default. Keys without history
+    /// are treated as seeing one ticket per window, so `threshold` tickets raise an alert.
+    pub threshold: f32,
+    /// Minimum time between two alerts for the same key, 1 hour by default
+    pub cooldown: Duration,
+}
+
+impl Default for SpikeConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::minutes(15),
+            baseline_window: Duration::hours(24),
+            threshold: 5.0,
+            cooldown: Duration::hours(1),
+        }
+    }
+}
+
+impl SpikeConfig {
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_baseline_window(mut self, baseline_window: Duration) -> Self {
+        self.baseline_window = baseline_window;
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+}
+
+#[derive(Default)]
+struct DetectorState {
+    /// When the first ticket was observed
+    started: Option<DateTime<Utc>>,
+    /// When keys without recent tickets were last removed
+    last_pruned: Option<DateTime<Utc>>,
+    keys: HashMap<String, KeyState>,
+}
+
+impl DetectorState {
+    /// Removes the keys that had no tickets within the baseline window and are not cooling
+    /// down, at most once per detection window, so that one-off products and clusters do not
+    /// accumulate
+    fn prune(&mut self, now: DateTime<Utc>, config: &SpikeConfig) {
+        if self
+            .last_pruned
+            .is_some_and(|last| now - last < config.window)
+        {
+            return;
+        }
+        self.last_pruned = Some(now);
+        let baseline_start = now - config.baseline_window;
+        self.keys.retain(|_, key_state| {
+            key_state.expire(baseline_start);
+            !key_state.arrivals.is_empty()
+                || key_state
+                    .last_alert
+                    .is_some_and(|last| now - last < config.cooldown)
+        });
+    }
+}
+
+#[derive(Default)]
+struct KeyState {
+    /// Arrival times and IDs of the tickets within the baseline window, oldest first
+    arrivals: VecDeque<(DateTime<Utc>, String)>,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+impl KeyState {
+    /// Drops the arrivals before `baseline_start`
+    fn expire(&mut self, baseline_start: DateTime<Utc>) {
+        while self
+            .arrivals
+            .front()
+            .is_some_and(|(arrived, _)| *arrived < baseline_start)
+        {
+            self.arrivals.pop_front();
+        }
+    }
+}
+
+/// Watches the stream of processed tickets for categories, products, keywords and duplicate
+/// clusters that suddenly rise above their normal rate, and sends incident alerts to the
+/// configured notifiers. Register it with `TicketPipeline::with_observer`.
+///
+/// Each ticket is counted under `category=<category>`, `product=<product>` and
+/// `cluster=<parent ticket>`, and under `category=<category> keyword=<keyword>` for each
+/// configured keyword it mentions. Messages that are not genuine are not counted.
+///
+/// Alerts start once a full baseline window has been observed, so that the first tickets
+/// after startup do not all look like spikes.
+pub struct SpikeDetector {
+    config: SpikeConfig,
+    clock: Arc<dyn Clock>,
+    notifiers: Vec<Arc<dyn IncidentNotifier>>,
+    keywords: Vec<(String, Regex)>,
+    state: Mutex<DetectorState>,
+}
+
+#[async_trait]
+impl TicketObserver for SpikeDetector {
+    async fn on_processed(&self, ticket: &ProcessedTicket) {
+        self.observe(ticket).await;
+    }
+}
+
+impl SpikeDetector {
+    pub fn new(config: SpikeConfig) -> Self {
+        Self {
+            config,
+            clock: Arc::new(SystemClock),
+            notifiers: Vec::new(),
+            keywords: Vec::new(),
+            state: Mutex::new(DetectorState::default()),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_notifier(mut self, notifier: Arc<dyn IncidentNotifier>) -> Self {
+        self.notifiers.push(notifier);
+        self
+    }
+
+    /// Keywords to track per category, matched as whole words ignoring case
+    pub fn with_keywords<S: AsRef<str>>(mut self, keywords: &[S]) -> Self {
+        self.keywords = keywords
+            .iter()
+            .map(|keyword| keyword.as_ref().trim())
+            .filter(|keyword| !keyword.is_empty())
+            .map(|keyword| (keyword.to_lowercase(), phrase_pattern(keyword)))
+            .collect();
+        self
+    }
+
+    /// Counts the ticket and sends an alert for every key that is spiking. Returns the alerts.
+    pub async fn observe(&self, ticket: &ProcessedTicket) -> Vec<IncidentAlert> {
+        if ticket.skip_reason().is_some() {
+            return Vec::new();
+        }
+        let now = self.clock.now();
+        let alerts: Vec<IncidentAlert> = {
+            let mut state = self.state.lock().unwrap();
+            let started = *state.started.get_or_insert(now);
+            let warmed_up = now - started >= self.config.baseline_window;
+            state.prune(now, &self.config);
+            self.keys(ticket)
+                .into_iter()
+                .filter_map(|key| {
+                    let key_state = state.keys.entry(key.clone()).or_default();
+                    self.record(key, key_state, &ticket.ticket.id, now, warmed_up)
+                })
+                .collect()
+        };
+
+        for alert in &alerts {
+            info!("Incident alert for {}", alert.key);
+            for notifier in &self.notifiers {
+                if let Err(e) = notifier.notify(alert).await {
+                    warn!("Failed to send incident alert for {}: {}", alert.key, e);
+                }
+            }
+        }
+        alerts
+    }
+
+    fn keys(&self, ticket: &ProcessedTicket) -> Vec<String> {
+        let mut keys = Vec::new();
+        if let ProcessingResult::Success(category) = &ticket.category {
+            let category = format!("category={:?}", category);
+            let text = match &ticket.ticket.subject {
+                Some(subject) => format!("{}\n{}", subject, ticket.ticket.content),
+                None => ticket.ticket.content.clone(),
+            };
+            for (keyword, pattern) in &self.keywords {
+                if pattern.is_match(&text) {
+                    keys.push(format!("{} keyword={}", category, keyword));
+                }
+            }
+            keys.push(category);
+        }
+        if let ProcessingResult::Success(entities) = &ticket.entities {
+            keys.extend(
+                entities
+                    .products
+                    .iter()
+                    .map(|product| format!("product={}", product)),
+            );
+        }
+        if let ProcessingResult::Success(Some(duplicate)) = &ticket.duplicate {
+            keys.push(format!("cluster={}", duplicate.parent_id));
+        }
+        keys
+    }
+
+    fn record(
+        &self,
+        key: String,
+        state: &mut KeyState,
+        ticket_id: &str,
+        now: DateTime<Utc>,
+        may_alert: bool,
+    ) -> Option<IncidentAlert> {
+        let config = &self.config;
+        state.expire(now - config.baseline_window);
+        state.arrivals.push_back((now, ticket_id.to_string()));
+
+        let window_start = now - config.window;
+        let recent: Vec<String> = state
+            .arrivals
+            .iter()
+            .filter(|(arrived, _)| *arrived >= window_start)
+            .map(|(_, id)| id.clone())
+            .collect();
+        let earlier = state.arrivals.len() - recent.len();
+        let baseline_windows = (config.baseline_window - config.window).num_seconds() as f32
+            / config.window.num_seconds().max(1) as f32;
+        let expected = (earlier as f32 / baseline_windows.max(1.0)).max(1.0);
+
+        let cooling_down = state
+            .last_alert
+            .is_some_and(|last| now - last < config.cooldown);
+        if may_alert && !cooling_down && recent.len() as f32 >= config.threshold * expected {
+            state.last_alert = Some(now);
+            Some(IncidentAlert {
+                key,
+                count: recent.len(),
+                expected,
+                window_minutes: config.window.num_minutes(),
+                detected_at: now,
+                ticket_ids: recent,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::{SupportTicket, TicketCategory};
+
+    struct RecordingNotifier(Mutex<Vec<IncidentAlert>>);
+
+    #[async_trait]
+    impl IncidentNotifier for RecordingNotifier {
+        async fn notify(&self, alert: &IncidentAlert) -> Result<(), ProcessingError> {
+            self.0.lock().unwrap().push(alert.clone());
+            Ok(())
+        }
+    }
+
+    fn ticket(id: usize, content: &str) -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            format!("t{}", id),
+            content.to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+        .with_category(ProcessingResult::Success(TicketCategory::Technical))
+    }
+
+    #[tokio::test]
+    async fn test_alerts_on_spike_above_baseline() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let notifier = Arc::new(RecordingNotifier(Mutex::new(Vec::new())));
+        let detector = SpikeDetector::new(
+            SpikeConfig::default()
+                .with_baseline_window(Duration::hours(4))
+                .with_threshold(5.0),
+        )
+        .with_clock(clock.clone())
+        .with_notifier(notifier.clone())
+        .with_keywords(&["login"]);
+
+        // Normally two login tickets and ten others an hour
+        let mut id = 0;
+        for _ in 0..8 {
+            for content in ["The login page is slow", "Export fails", "Export fails"]
+                .iter()
+                .chain(["Sync is slow"; 3].iter())
+            {
+                id += 1;
+                assert!(detector.observe(&ticket(id, content)).await.is_empty());
+            }
+            clock.advance(Duration::minutes(30));
+        }
+
+        // Ten login tickets in ten minutes
+        let mut alerts = Vec::new();
+        for _ in 0..10 {
+            id += 1;
+            alerts.extend(
+                detector
+                    .observe(&ticket(id, "Login fails with error 500"))
+                    .await,
+            );
+            clock.advance(Duration::minutes(1));
+        }
+
+        // Login tickets spiked, Technical tickets as a whole did not
+        assert_eq!(alerts.len(), 1);
+        let alert = &alerts[0];
+        assert_eq!(alert.key, "category=Technical keyword=login");
+        assert_eq!(alert.count, 5);
+        assert_eq!(alert.ticket_ids, vec!["t49", "t50", "t51", "t52", "t53"]);
+        assert_eq!(alert.expected, 1.0);
+        assert_eq!(*notifier.0.lock().unwrap(), alerts);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_cooldown_and_skipped_messages() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let detector = SpikeDetector::new(
+            SpikeConfig::default()
+                .with_threshold(3.0)
+                .with_cooldown(Duration::minutes(30)),
+        )
+        .with_clock(clock.clone());
+
+        // No alerts before a full baseline window has been observed
+        for id in 0..5 {
+            assert!(detector.observe(&ticket(id, "Broken")).await.is_empty());
+        }
+        clock.advance(Duration::hours(25));
+
+        let spam = ticket(0, "Buy now")
+            .with_message_kind(ProcessingResult::Success(crate::ticket::MessageKind::Spam));
+        for _ in 0..5 {
+            assert!(detector.observe(&spam).await.is_empty());
+        }
+
+        let mut alert_minutes = Vec::new();
+        for minute in (0..60).step_by(3) {
+            if !detector.observe(&ticket(minute, "Broken")).await.is_empty() {
+                alert_minutes.push(minute);
+            }
+            clock.advance(Duration::minutes(3));
+        }
+
+        assert_eq!(alert_minutes, vec![6, 36]);
+    }
+
+    #[tokio::test]
+    async fn test_forgets_keys_without_recent_tickets() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let detector = SpikeDetector::new(
+            SpikeConfig::default()
+                .with_baseline_window(Duration::hours(1))
+                .with_threshold(2.0)
+                .with_cooldown(Duration::hours(2)),
+        )
+        .with_clock(clock.clone());
+        let uncategorized =
+            |id| ticket(id, "Hi").with_category(ProcessingResult::Skipped(String::new()));
+        detector.observe(&uncategorized(0)).await;
+        clock.advance(Duration::hours(1));
+        // Alerts on Technical, starting its cooldown
+        detector.observe(&ticket(1, "Broken")).await;
+        let product = ticket(2, "Broken").with_entities(ProcessingResult::Success(
+            crate::ticket::TicketEntities {
+                products: vec!["Sync".to_string()],
+                ..Default::default()
+            },
+        ));
+        assert_eq!(detector.observe(&product).await.len(), 1);
+        let keys = |detector: &SpikeDetector| {
+            let mut keys: Vec<_> = detector
+                .state
+                .lock()
+                .unwrap()
+                .keys
+                .keys()
+                .cloned()
+                .collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys(&detector), vec!["category=Technical", "product=Sync"]);
+
+        // Both keys are past the baseline window, but Technical is still cooling down
+        clock.advance(Duration::minutes(90));
+        detector.observe(&uncategorized(3)).await;
+        assert_eq!(keys(&detector), vec!["category=Technical"]);
+
+        clock.advance(Duration::hours(1));
+        detector.observe(&uncategorized(4)).await;
+        assert!(keys(&detector).is_empty());
+    }
+}
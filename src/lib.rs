@@ -1,5 +1,12 @@
 pub mod error;
+pub mod incidents;
+pub mod llm;
 pub mod pipeline;
 pub mod processors;
+pub mod prompt;
 pub mod ticket;
 pub mod ticket_store;
+pub mod truncation;
+
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,104 @@
+use std::{env, time::Duration};
+
+use async_openai::{Client, config::OpenAIConfig};
+
+use crate::error::ProcessingError;
+
+/// Settings for processors that talk to the OpenAI API or an OpenAI-compatible server
+/// (Azure, a local inference server or a mock server in tests).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmConfig {
+    pub model: String,
+    /// Base URL of the API, e.g. `http://localhost:8080/v1`. Uses the OpenAI API when `None`.
+    pub api_base: Option<String>,
+    /// API key to send. Falls back to the `OPENAI_API_KEY` environment variable when `None`.
+    pub api_key: Option<String>,
+    pub organization: Option<String>,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub timeout: Duration,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            model: "gpt-4.1-nano".to_string(),
+            api_base: None,
+            api_key: None,
+            organization: None,
+            max_tokens: 50,
+            temperature: 0.0,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl LlmConfig {
+    /// Builds a config from the `OPENAI_MODEL`, `OPENAI_API_BASE` and `OPENAI_ORG_ID`
+    /// environment variables, using the defaults for anything that is not set.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            model: env::var("OPENAI_MODEL").unwrap_or(defaults.model),
+            api_base: env::var("OPENAI_API_BASE").ok(),
+            organization: env::var("OPENAI_ORG_ID").ok(),
+            ..defaults
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    pub fn with_api_base(mut self, api_base: impl Into<String>) -> Self {
+        self.api_base = Some(api_base.into());
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Creates an OpenAI client for this configuration
+    pub fn client(&self) -> Result<Client<OpenAIConfig>, ProcessingError> {
+        let mut config = OpenAIConfig::new();
+        if let Some(api_base) = &self.api_base {
+            config = config.with_api_base(api_base);
+        }
+        if let Some(api_key) = &self.api_key {
+            config = config.with_api_key(api_key);
+        }
+        if let Some(organization) = &self.organization {
+            config = config.with_org_id(organization);
+        }
+
+        let http_client = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| ProcessingError::NetworkError(e.to_string()))?;
+
+        Ok(Client::with_config(config).with_http_client(http_client))
+    }
+}
@@ -1,14 +1,30 @@
 use chrono::{DateTime, Utc};
 use dotenvy::dotenv;
-use std::io;
 use std::sync::Arc;
+use std::{env, io};
 use ticket_triage::{
+    incidents::{LogNotifier, SpikeConfig, SpikeDetector, WebhookNotifier},
+    llm::LlmConfig,
     pipeline::TicketPipeline,
     processors::{
-        classification::ClassificationProcessor, language::LanguageProcessor,
-        priority::PriorityProcessor, sentiment::SentimentProcessor,
+        churn::ChurnRiskProcessor,
+        classification::ClassificationProcessor,
+        duplicates::DuplicateProcessor,
+        entities::EntityProcessor,
+        language::{LanguageProcessor, language_from_locale, language_name},
+        message_kind::MessageKindProcessor,
+        priority::PriorityProcessor,
+        redaction::RedactionProcessor,
+        reply_draft::ReplyDraftProcessor,
+        routing::{RoutingConfig, RoutingProcessor},
+        sentiment::SentimentProcessor,
+        summary::SummarizationProcessor,
+        translation::TranslationProcessor,
+        urgency::UrgencyProcessor,
     },
-    ticket::{ProcessingResult, SupportTicket},
+    prompt::PromptTemplate,
+    ticket::{ContentSource, ProcessingResult, SupportTicket},
+    ticket_store::TicketStore,
 };
 
 #[tokio::main]
@@ -33,15 +49,149 @@ async fn main() {
     let timestamp: DateTime<Utc> = Utc::now();
     let customer_id = "customer1".to_string();
 
-    let ticket = SupportTicket::new("t1".to_string(), ticket_content, timestamp, customer_id);
+    let mut ticket = SupportTicket::new("t1".to_string(), ticket_content, timestamp, customer_id);
+    if let Ok(tier) = env::var("CUSTOMER_TIER") {
+        ticket = ticket.with_customer_tier(tier);
+    }
+    if let Ok(locale) = env::var("CUSTOMER_LOCALE") {
+        ticket = ticket.with_customer_locale(locale);
+    }
 
-    let pipeline = Arc::new(
-        TicketPipeline::default()
-            .with_processor(Arc::new(LanguageProcessor))
-            .with_processor(Arc::new(SentimentProcessor::new().unwrap()))
-            .with_processor(Arc::new(ClassificationProcessor::new().unwrap()))
-            .with_processor(Arc::new(PriorityProcessor::new().unwrap())),
-    );
+    let mut language_processor = LanguageProcessor::new();
+    if let Ok(locale) = env::var("DEFAULT_LANGUAGE") {
+        let language = language_from_locale(&locale).expect("Unknown default language");
+        language_processor = language_processor.with_default_language(language);
+    }
+    let language_list = |var: &str| {
+        env::var(var).ok().map(|codes| {
+            codes
+                .split(',')
+                .map(|code| language_from_locale(code.trim()).expect("Unknown language code"))
+                .collect::<Vec<_>>()
+        })
+    };
+    if let Some(allowlist) = language_list("LANGUAGE_ALLOWLIST") {
+        language_processor = language_processor
+            .with_allowlist(&allowlist)
+            .expect("Invalid LANGUAGE_ALLOWLIST");
+    } else if let Some(denylist) = language_list("LANGUAGE_DENYLIST") {
+        language_processor = language_processor
+            .with_denylist(&denylist)
+            .expect("Invalid LANGUAGE_DENYLIST");
+    }
+
+    let mut classification_processor = ClassificationProcessor::with_config(LlmConfig::from_env())
+        .unwrap()
+        .with_language_aware_prompts();
+    if let Ok(path) = env::var("CLASSIFICATION_PROMPT_FILE") {
+        let template = PromptTemplate::from_file(&path).expect("Failed to load prompt template");
+        classification_processor = classification_processor.with_prompt_template(template);
+    }
+
+    let mut entity_processor = EntityProcessor::new();
+    if let Ok(path) = env::var("PRODUCT_CATALOG_FILE") {
+        entity_processor = entity_processor
+            .with_product_catalog(&path)
+            .expect("Failed to load product catalog");
+    }
+
+    // With translation enabled, the urgency and churn rules are matched against the English
+    // translation of the ticket
+    let translation_processor = env::var("TRANSLATE_TICKETS")
+        .is_ok_and(|translate| translate == "true" || translate == "1")
+        .then(|| {
+            TranslationProcessor::with_openai(LlmConfig::from_env().with_max_tokens(2000)).unwrap()
+        });
+    let content_source = if translation_processor.is_some() {
+        ContentSource::Translated
+    } else {
+        ContentSource::Original
+    };
+
+    let urgency_processor = match env::var("URGENCY_RULES_FILE") {
+        Ok(path) => UrgencyProcessor::from_file(&path).expect("Failed to load urgency rules"),
+        Err(_) => UrgencyProcessor::new(),
+    }
+    .with_content_source(content_source);
+
+    let ticket_store = TicketStore::new();
+    let competitors: Vec<String> = env::var("CHURN_COMPETITORS")
+        .map(|names| {
+            names
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let duplicate_processor = DuplicateProcessor::new(ticket_store.clone());
+    let churn_processor = ChurnRiskProcessor::new(ticket_store.clone())
+        .with_competitors(&competitors)
+        .with_content_source(content_source);
+
+    let keywords: Vec<String> = env::var("INCIDENT_KEYWORDS")
+        .map(|keywords| {
+            keywords
+                .split(',')
+                .map(|keyword| keyword.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut spike_detector = SpikeDetector::new(SpikeConfig::default())
+        .with_keywords(&keywords)
+        .with_notifier(Arc::new(LogNotifier));
+    if let Ok(url) = env::var("INCIDENT_WEBHOOK_URL") {
+        spike_detector = spike_detector.with_notifier(Arc::new(WebhookNotifier::new(&url)));
+    }
+
+    let routing_processor = env::var("ROUTING_CONFIG_FILE").ok().map(|path| {
+        let config = RoutingConfig::from_file(&path).expect("Failed to load routing config");
+        RoutingProcessor::new(config, ticket_store.clone()).expect("Invalid routing config")
+    });
+
+    let reply_draft_processor = env::var("REPLY_TEMPLATES_DIR").ok().map(|dir| {
+        let processor =
+            ReplyDraftProcessor::from_dir(&dir).expect("Failed to load reply templates");
+        if env::var("REPLY_POLISH").is_ok_and(|polish| polish == "true" || polish == "1") {
+            processor
+                .with_llm_polish(LlmConfig::from_env().with_max_tokens(500))
+                .unwrap()
+        } else {
+            processor
+        }
+    });
+
+    // Summaries are picked from the ticket's sentences when no LLM is configured
+    let llm_config = LlmConfig::from_env().with_max_tokens(300);
+    let summarization_processor = if llm_config.api_key.is_some() || llm_config.api_base.is_some() {
+        SummarizationProcessor::new().with_llm(llm_config).unwrap()
+    } else {
+        SummarizationProcessor::new()
+    };
+
+    let mut pipeline = TicketPipeline::default()
+        .with_ticket_store(ticket_store)
+        .with_processor(Arc::new(language_processor))
+        .with_processor(Arc::new(MessageKindProcessor::new()))
+        .with_processor(Arc::new(duplicate_processor))
+        .with_processor(Arc::new(RedactionProcessor::new()))
+        .with_processor(Arc::new(entity_processor))
+        .with_processor(Arc::new(urgency_processor))
+        .with_processor(Arc::new(churn_processor))
+        .with_processor(Arc::new(SentimentProcessor::new().unwrap()))
+        .with_processor(Arc::new(classification_processor))
+        .with_processor(Arc::new(PriorityProcessor::new().unwrap()))
+        .with_processor(Arc::new(summarization_processor))
+        .with_observer(Arc::new(spike_detector));
+    if let Some(translation_processor) = translation_processor {
+        pipeline = pipeline.with_processor(Arc::new(translation_processor));
+    }
+    if let Some(routing_processor) = routing_processor {
+        pipeline = pipeline.with_processor(Arc::new(routing_processor));
+    }
+    if let Some(reply_draft_processor) = reply_draft_processor {
+        pipeline = pipeline.with_processor(Arc::new(reply_draft_processor));
+    }
+    let pipeline = Arc::new(pipeline);
 
     // Start the pipeline processing loop in the background
     let pipeline_clone = Arc::clone(&pipeline);
@@ -75,22 +225,36 @@ async fn main() {
     println!("👤 Customer ID: {}", processed_ticket.ticket.customer_id);
 
     match &processed_ticket.language {
-        ProcessingResult::Success(language) => {
-            println!("🌍 Language: {:?}", language);
-        }
+        ProcessingResult::Success(language) => match &processed_ticket.language_detection {
+            ProcessingResult::Success(detection) => println!(
+                "🌍 Language: {:?} ({:?}, confidence {:.2}{})",
+                language,
+                detection.source,
+                detection.confidence,
+                if detection.reliable {
+                    ""
+                } else {
+                    ", unreliable"
+                }
+            ),
+            _ => println!("🌍 Language: {:?}", language),
+        },
         ProcessingResult::Processing => {
             println!("🌍 Language: Processing...");
         }
         ProcessingResult::Error(err) => {
             println!("🌍 Language: Error - {:?}", err);
         }
+        ProcessingResult::Skipped(reason) => {
+            println!("🌍 Language: Skipped - {}", reason);
+        }
     }
 
     match &processed_ticket.sentiment {
         ProcessingResult::Success(sentiment) => {
             println!(
-                "😊 Sentiment: {:?} (confidence: {:.2})",
-                sentiment.label, sentiment.confidence
+                "😊 Sentiment: {:?} (confidence: {:.2}, polarity: {:.2})",
+                sentiment.label, sentiment.confidence, sentiment.polarity
             );
         }
         ProcessingResult::Processing => {
@@ -99,6 +263,9 @@ async fn main() {
         ProcessingResult::Error(err) => {
             println!("😊 Sentiment: Error - {:?}", err);
         }
+        ProcessingResult::Skipped(reason) => {
+            println!("😊 Sentiment: Skipped - {}", reason);
+        }
     }
 
     match &processed_ticket.category {
@@ -111,6 +278,67 @@ async fn main() {
         ProcessingResult::Error(err) => {
             println!("📂 Category: Error - {:?}", err);
         }
+        ProcessingResult::Skipped(reason) => {
+            println!("📂 Category: Skipped - {}", reason);
+        }
+    }
+
+    if let ProcessingResult::Success(redaction) = &processed_ticket.redaction
+        && !redaction.pii_types.is_empty()
+    {
+        println!("🔒 Redacted PII: {:?}", redaction.pii_types);
+    }
+
+    if let ProcessingResult::Success(entities) = &processed_ticket.entities
+        && entities != &Default::default()
+    {
+        println!("🔎 Entities: {:?}", entities);
+    }
+
+    if let ProcessingResult::Success(urgency) = &processed_ticket.urgency {
+        for signal in urgency {
+            println!("🚨 Urgency: {} (\"{}\")", signal.kind, signal.phrase);
+        }
+    }
+
+    if let ProcessingResult::Success(Some(translation)) = &processed_ticket.translation {
+        println!(
+            "🔤 Translation from {}: {}",
+            language_name(&translation.source_language),
+            translation.content
+        );
+    }
+    if let ProcessingResult::Success(summary) = &processed_ticket.summary {
+        println!("🧾 Summary: {}", summary.title);
+        for bullet in &summary.bullets {
+            println!("   • {}", bullet);
+        }
+    }
+    if let ProcessingResult::Success(kind) = &processed_ticket.message_kind {
+        println!("✉️  Message kind: {:?}", kind);
+    }
+    if let ProcessingResult::Success(Some(duplicate)) = &processed_ticket.duplicate {
+        println!(
+            "🔁 Duplicate of {} ({:.0}% similar, {} tickets in cluster)",
+            duplicate.parent_id,
+            duplicate.similarity * 100.0,
+            duplicate.cluster_size
+        );
+    }
+    if let ProcessingResult::Success(routing) = &processed_ticket.routing {
+        match &routing.agent {
+            Some(agent) => println!(
+                "🧭 Routed to {} (queue {}), assigned to {}",
+                routing.team, routing.queue, agent
+            ),
+            None => println!("🧭 Routed to {} (queue {})", routing.team, routing.queue),
+        }
+    }
+    if let ProcessingResult::Success(churn_risk) = &processed_ticket.churn_risk {
+        println!(
+            "📉 Churn risk: {:?} {:?}",
+            churn_risk.level, churn_risk.evidence
+        );
     }
 
     match &processed_ticket.priority {
@@ -123,6 +351,19 @@ async fn main() {
         ProcessingResult::Error(err) => {
             println!("⚡ Priority: Error - {:?}", err);
         }
+        ProcessingResult::Skipped(reason) => {
+            println!("⚡ Priority: Skipped - {}", reason);
+        }
+    }
+
+    if let ProcessingResult::Success(Some(draft)) = &processed_ticket.reply_draft {
+        println!(
+            "✍️  Suggested reply ({}, score {:.1}{}):\n{}",
+            draft.template,
+            draft.score,
+            if draft.polished { ", polished" } else { "" },
+            draft.text
+        );
     }
 
     println!("{}", "=".repeat(60));
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use async_trait::async_trait;
 use log::{info, warn};
@@ -17,12 +17,26 @@ pub trait TicketProcessor: Sync + Send {
 
     fn required_fields(&self) -> FieldMask;
 
+    /// Fields this processor waits for when a configured processor produces them, but which do
+    /// not hold it back otherwise, such as the redacted content
+    fn optional_fields(&self) -> FieldMask {
+        FieldMask::empty()
+    }
+
     /// Returns the fields that this processor produces/updates
     fn output_fields(&self) -> FieldMask;
 }
 
+/// Receives every ticket once, when all configured processors have finished with it, e.g. to
+/// aggregate statistics over the ticket stream
+#[async_trait]
+pub trait TicketObserver: Sync + Send {
+    async fn on_processed(&self, ticket: &ProcessedTicket);
+}
+
 pub struct TicketPipeline {
     processors: Vec<Arc<dyn TicketProcessor>>,
+    observers: Vec<Arc<dyn TicketObserver>>,
     ticket_store: Arc<TicketStore>,
     event_sender: Arc<broadcast::Sender<TicketUpdateEvent>>,
 }
@@ -31,6 +45,7 @@ impl Default for TicketPipeline {
     fn default() -> Self {
         Self {
             processors: Vec::new(),
+            observers: Vec::new(),
             ticket_store: Arc::new(TicketStore::default()),
             event_sender: Arc::new(broadcast::channel::<TicketUpdateEvent>(16).0),
         }
@@ -43,6 +58,22 @@ impl TicketPipeline {
         self
     }
 
+    pub fn with_observer(mut self, observer: Arc<dyn TicketObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Uses the given store, so that processors that look at other tickets, such as churn
+    /// risk detection, can share it with the pipeline
+    pub fn with_ticket_store(mut self, ticket_store: TicketStore) -> Self {
+        self.ticket_store = Arc::new(ticket_store);
+        self
+    }
+
+    pub fn ticket_store(&self) -> &TicketStore {
+        &self.ticket_store
+    }
+
     pub async fn run(&self) -> Result<(), ProcessingError> {
         if self.processors.is_empty() {
             return Err(ProcessingError::TicketProcessingError(
@@ -50,15 +81,29 @@ impl TicketPipeline {
             ));
         }
 
+        let produced_fields = self.produced_fields();
+        for processor in &self.processors {
+            let missing_fields = processor.required_fields() - produced_fields;
+            if !missing_fields.is_empty() {
+                warn!(
+                    "No configured processor produces the required fields {:?}, they must be set outside the pipeline",
+                    missing_fields
+                );
+            }
+        }
         for processor in &self.processors {
             let ticket_store_clone = Arc::clone(&self.ticket_store);
             let event_sender_clone = Arc::clone(&self.event_sender);
             let processor_clone = Arc::clone(processor);
+            // Subscribe before spawning so events sent right after `run` returns are not missed
+            let mut rx = event_sender_clone.subscribe();
 
             tokio::spawn(async move {
-                let mut rx = event_sender_clone.subscribe();
                 while let Ok(event) = rx.recv().await {
-                    let required_fields = processor_clone.required_fields();
+                    // Optional dependencies on fields no configured processor produces are
+                    // ignored, so processors such as redaction can be left out of the pipeline
+                    let required_fields = processor_clone.required_fields()
+                        | (processor_clone.optional_fields() & produced_fields);
 
                     if let Some(ticket) = ticket_store_clone.get_ticket(&event.ticket_id).await {
                         let current_fields = FieldMask::from(&ticket);
@@ -82,14 +127,14 @@ impl TicketPipeline {
                             let ticket_id = ticket.ticket.id.clone();
                             let updated_ticket = processor_clone.process(ticket).await;
 
-                            let updated_fields = FieldMask::from(&updated_ticket);
                             let updated = ticket_store_clone
                                 .update_ticket(&ticket_id, |t| {
                                     t.merge_from(updated_ticket);
                                 })
                                 .await;
 
-                            if updated.is_some() {
+                            if let Some(updated) = updated {
+                                let updated_fields = FieldMask::from(&updated);
                                 info!(
                                     "Processor completed processing for ticket: {} with updated fields: {:?}",
                                     ticket_id, updated_fields
@@ -105,6 +150,29 @@ impl TicketPipeline {
             });
         }
 
+        if !self.observers.is_empty() {
+            let ticket_store_clone = Arc::clone(&self.ticket_store);
+            let observers = self.observers.clone();
+            let mut rx = self.event_sender.subscribe();
+
+            tokio::spawn(async move {
+                // Complete tickets can be announced again, e.g. by `notify_updated`
+                let mut announced = HashSet::new();
+                while let Ok(event) = rx.recv().await {
+                    if !event.completed_fields.contains(produced_fields)
+                        || !announced.insert(event.ticket_id.clone())
+                    {
+                        continue;
+                    }
+                    if let Some(ticket) = ticket_store_clone.get_ticket(&event.ticket_id).await {
+                        for observer in &observers {
+                            observer.on_processed(&ticket).await;
+                        }
+                    }
+                }
+            });
+        }
+
         Ok(())
     }
 
@@ -115,6 +183,8 @@ impl TicketPipeline {
         info!("Starting to process ticket: {}", ticket.id);
         let processed_ticket = ProcessedTicket::new(ticket);
         self.ticket_store.add_ticket(processed_ticket.clone()).await;
+        // Subscribe before announcing the ticket so fast processors cannot finish unobserved
+        let rx = self.event_sender.subscribe();
         self.event_sender
             .send(TicketUpdateEvent {
                 ticket_id: processed_ticket.ticket.id.clone(),
@@ -125,7 +195,7 @@ impl TicketPipeline {
             })?;
 
         let result = self
-            .wait_for_processing(processed_ticket.ticket.id.clone())
+            .wait_for_processing(processed_ticket.ticket.id.clone(), rx)
             .await;
 
         match &result {
@@ -142,18 +212,40 @@ impl TicketPipeline {
         result
     }
 
+    /// Announces a ticket that was updated outside the pipeline, e.g. by bulk classification,
+    /// so that the processors waiting for its new fields run
+    pub async fn notify_updated(&self, ticket_id: &str) {
+        if let Some(ticket) = self.ticket_store.get_ticket(ticket_id).await {
+            // Fails only when no processor is listening, i.e. the pipeline is not running
+            let _ = self.event_sender.send(TicketUpdateEvent {
+                ticket_id: ticket_id.to_string(),
+                completed_fields: FieldMask::from(&ticket),
+            });
+        }
+    }
+
+    /// The fields produced by the configured processors
+    fn produced_fields(&self) -> FieldMask {
+        self.processors
+            .iter()
+            .fold(FieldMask::empty(), |fields, processor| {
+                fields | processor.output_fields()
+            })
+    }
+
     async fn wait_for_processing(
         &self,
         ticket_id: String,
+        mut rx: broadcast::Receiver<TicketUpdateEvent>,
     ) -> Result<ProcessedTicket, ProcessingError> {
-        let mut rx = self.event_sender.subscribe();
+        let expected_fields = self.produced_fields();
 
         loop {
             match rx.recv().await {
                 Ok(TicketUpdateEvent {
                     ticket_id: id,
                     completed_fields,
-                }) if id == ticket_id && completed_fields == FieldMask::all() => {
+                }) if id == ticket_id && completed_fields.contains(expected_fields) => {
                     break;
                 }
                 Ok(_) => continue,
@@ -184,6 +276,16 @@ bitflags! {
         const SENTIMENT = 0b0010;
         const CATEGORY = 0b0100;
         const PRIORITY = 0b1000;
+        const REDACTION = 0b1_0000;
+        const ENTITIES = 0b10_0000;
+        const URGENCY = 0b100_0000;
+        const CHURN_RISK = 0b1000_0000;
+        const MESSAGE_KIND = 0b1_0000_0000;
+        const DUPLICATE = 0b10_0000_0000;
+        const ROUTING = 0b100_0000_0000;
+        const REPLY_DRAFT = 0b1000_0000_0000;
+        const SUMMARY = 0b1_0000_0000_0000;
+        const TRANSLATION = 0b10_0000_0000_0000;
     }
 }
 
@@ -206,6 +308,46 @@ impl From<&ProcessedTicket> for FieldMask {
             ProcessingResult::Processing => {}
             _ => mask.insert(FieldMask::PRIORITY),
         }
+        match ticket.redaction {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::REDACTION),
+        }
+        match ticket.entities {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::ENTITIES),
+        }
+        match ticket.urgency {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::URGENCY),
+        }
+        match ticket.churn_risk {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::CHURN_RISK),
+        }
+        match ticket.message_kind {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::MESSAGE_KIND),
+        }
+        match ticket.duplicate {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::DUPLICATE),
+        }
+        match ticket.routing {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::ROUTING),
+        }
+        match ticket.reply_draft {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::REPLY_DRAFT),
+        }
+        match ticket.summary {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::SUMMARY),
+        }
+        match ticket.translation {
+            ProcessingResult::Processing => {}
+            _ => mask.insert(FieldMask::TRANSLATION),
+        }
         mask
     }
 }
@@ -249,4 +391,134 @@ mod tests {
         assert!(mask.contains(FieldMask::SENTIMENT));
         assert!(!mask.contains(FieldMask::CATEGORY));
     }
+
+    /// Records the content it would send to an external service as the category error message
+    struct ExternalStub;
+
+    #[async_trait]
+    impl TicketProcessor for ExternalStub {
+        async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+            let seen = ticket.external_content().to_string();
+            ticket.with_category(ProcessingResult::Error(
+                ProcessingError::ClassificationError(seen),
+            ))
+        }
+
+        fn required_fields(&self) -> FieldMask {
+            FieldMask::empty()
+        }
+
+        fn optional_fields(&self) -> FieldMask {
+            FieldMask::REDACTION
+        }
+
+        fn output_fields(&self) -> FieldMask {
+            FieldMask::CATEGORY
+        }
+    }
+
+    #[tokio::test]
+    async fn test_external_processors_wait_for_redaction() {
+        let pipeline = TicketPipeline::default()
+            .with_processor(Arc::new(ExternalStub))
+            .with_processor(Arc::new(
+                crate::processors::redaction::RedactionProcessor::new(),
+            ));
+        pipeline.run().await.unwrap();
+        let mut ticket = create_test_ticket();
+        ticket.content = "Mail me at ann@example.com".to_string();
+
+        let processed = pipeline.process_ticket(ticket).await.unwrap();
+
+        assert_eq!(
+            processed.category,
+            ProcessingResult::Error(ProcessingError::ClassificationError(
+                "Mail me at [EMAIL]".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dependencies_on_unconfigured_fields_are_ignored() {
+        let pipeline = TicketPipeline::default().with_processor(Arc::new(ExternalStub));
+        pipeline.run().await.unwrap();
+
+        let processed = pipeline.process_ticket(create_test_ticket()).await.unwrap();
+
+        assert_eq!(processed.redaction, ProcessingResult::Processing);
+        assert_eq!(
+            processed.category,
+            ProcessingResult::Error(ProcessingError::ClassificationError(
+                "Test ticket content".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_required_fields_are_not_ignored() {
+        let store = TicketStore::new();
+        let pipeline = TicketPipeline::default()
+            .with_ticket_store(store.clone())
+            .with_processor(Arc::new(ExternalStub))
+            .with_processor(Arc::new(
+                crate::processors::priority::PriorityProcessor::new().unwrap(),
+            ));
+        pipeline.run().await.unwrap();
+        store
+            .add_ticket(ProcessedTicket::new(create_test_ticket()))
+            .await;
+
+        // Priority requires the sentiment, which no configured processor produces
+        pipeline.notify_updated("test-1").await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let ticket = store.get_ticket("test-1").await.unwrap();
+        assert!(matches!(ticket.category, ProcessingResult::Error(_)));
+        assert_eq!(ticket.priority, ProcessingResult::Processing);
+
+        store
+            .update_ticket("test-1", |ticket| {
+                ticket.sentiment =
+                    ProcessingResult::Error(ProcessingError::InvalidTicketData("test".to_string()));
+            })
+            .await;
+        pipeline.notify_updated("test-1").await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let ticket = store.get_ticket("test-1").await.unwrap();
+        assert!(!matches!(ticket.priority, ProcessingResult::Processing));
+    }
+
+    struct ForwardingObserver(tokio::sync::mpsc::UnboundedSender<ProcessedTicket>);
+
+    #[async_trait]
+    impl TicketObserver for ForwardingObserver {
+        async fn on_processed(&self, ticket: &ProcessedTicket) {
+            self.0.send(ticket.clone()).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observers_receive_completed_tickets_once() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let pipeline = TicketPipeline::default()
+            .with_processor(Arc::new(ExternalStub))
+            .with_processor(Arc::new(
+                crate::processors::redaction::RedactionProcessor::new(),
+            ))
+            .with_observer(Arc::new(ForwardingObserver(tx)));
+        pipeline.run().await.unwrap();
+
+        pipeline.process_ticket(create_test_ticket()).await.unwrap();
+
+        let observed = tokio::time::timeout(std::time::Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(observed.ticket.id, "test-1");
+        assert!(FieldMask::from(&observed).contains(FieldMask::CATEGORY | FieldMask::REDACTION));
+
+        // Announcing a complete ticket again, e.g. after bulk classification, is not a new ticket
+        pipeline.notify_updated("test-1").await;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+    }
 }
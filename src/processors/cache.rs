@@ -0,0 +1,653 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    error::ProcessingError,
+    pipeline::{FieldMask, TicketProcessor},
+    ticket::{ProcessedTicket, ProcessingResult},
+};
+use async_trait::async_trait;
+use log::{info, warn};
+use serde_json::{Map, Value};
+
+/// Storage for cached processor results. Values are JSON strings.
+#[async_trait]
+pub trait CacheBackend: Sync + Send {
+    async fn get(&self, key: &str) -> Option<String>;
+
+    async fn put(&self, key: &str, value: String);
+}
+
+/// Caches the results of an expensive processor, e.g. one calling the OpenAI or Hugging Face
+/// APIs, so that duplicate and templated tickets are only processed once.
+///
+/// The cache key is a hash of the normalized ticket subject and content, the processor version
+/// and the values of the fields the processor reads, both required and optional. Only successful results are cached, along with
+/// the review signals, prompt versions and language details the processor added.
+pub struct CachedProcessor {
+    inner: Arc<dyn TicketProcessor>,
+    version: String,
+    backend: Arc<dyn CacheBackend>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[async_trait]
+impl TicketProcessor for CachedProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        let key = match self.cache_key(&ticket) {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("Failed to build cache key, bypassing cache: {:?}", e);
+                return self.inner.process(ticket).await;
+            }
+        };
+
+        if let Some(cached) = self.backend.get(&key).await {
+            match apply_fields(&ticket, &cached) {
+                Ok(result) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    info!(
+                        "Cache hit for ticket {} ({})",
+                        ticket.ticket.id, self.version
+                    );
+                    return result;
+                }
+                Err(e) => warn!("Ignoring unreadable cache entry {}: {:?}", key, e),
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let original = ticket.clone();
+        let result = self.inner.process(ticket).await;
+
+        match extract_fields(&result, self.output_fields()) {
+            Ok(mut fields) if all_succeeded(&fields) => {
+                fields.extend(extract_companions(&original, &result));
+                self.backend
+                    .put(&key, Value::Object(fields).to_string())
+                    .await
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to cache result: {:?}", e),
+        }
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        self.inner.required_fields()
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        self.inner.optional_fields()
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        self.inner.output_fields()
+    }
+}
+
+impl CachedProcessor {
+    /// `version` identifies the processor and its configuration, e.g.
+    /// `"classification/gpt-4.1-nano/v2"`. Changing it invalidates earlier entries.
+    pub fn new(
+        inner: Arc<dyn TicketProcessor>,
+        version: impl Into<String>,
+        backend: Arc<dyn CacheBackend>,
+    ) -> Self {
+        Self {
+            inner,
+            version: version.into(),
+            backend,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn cache_key(&self, ticket: &ProcessedTicket) -> Result<String, ProcessingError> {
+        let inputs = extract_fields(
+            ticket,
+            self.inner.required_fields() | self.inner.optional_fields(),
+        )?;
+        let key_material = format!(
+            "{}\u{0}{}\u{0}{}\u{0}{}",
+            self.version,
+            normalize_content(ticket.ticket.subject.as_deref().unwrap_or_default()),
+            normalize_content(&ticket.ticket.content),
+            Value::Object(inputs)
+        );
+        Ok(format!("{:016x}", fnv1a_64(key_material.as_bytes())))
+    }
+}
+
+/// Lowercases the content and collapses whitespace so trivially different copies share an entry
+fn normalize_content(content: &str) -> String {
+    content
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A stable 64 bit hash, unlike `DefaultHasher` which may change between Rust releases and
+/// would invalidate the on-disk cache
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Name of the `ProcessedTicket` field for each flag, which is the lowercase flag name
+fn field_names(fields: FieldMask) -> impl Iterator<Item = String> {
+    fields.iter_names().map(|(name, _)| name.to_lowercase())
+}
+
+/// Returns the serialized values of the given fields of the ticket
+fn extract_fields(
+    ticket: &ProcessedTicket,
+    fields: FieldMask,
+) -> Result<Map<String, Value>, ProcessingError> {
+    let Value::Object(mut all) =
+        serde_json::to_value(ticket).map_err(|e| ProcessingError::UnknownError(e.to_string()))?
+    else {
+        return Err(ProcessingError::UnknownError(
+            "Ticket did not serialize to an object".to_string(),
+        ));
+    };
+
+    field_names(fields)
+        .map(|name| {
+            all.remove(&name)
+                .map(|value| (name.clone(), value))
+                .ok_or_else(|| ProcessingError::UnknownError(format!("Unknown field {}", name)))
+        })
+        .collect()
+}
+
+/// Returns what the processor added to the fields outside the `FieldMask`: its review signals
+/// and prompt versions, and the language details of the language processor
+fn extract_companions(
+    original: &ProcessedTicket,
+    processed: &ProcessedTicket,
+) -> Map<String, Value> {
+    let mut companions = Map::new();
+    let signals: Vec<_> = processed
+        .review_signals
+        .iter()
+        .filter(|signal| !original.review_signals.contains(signal))
+        .collect();
+    if !signals.is_empty() {
+        companions.insert("review_signals".to_string(), serde_json::json!(signals));
+    }
+    let versions: Map<String, Value> = processed
+        .prompt_versions
+        .iter()
+        .filter(|(key, version)| original.prompt_versions.get(*key) != Some(*version))
+        .map(|(key, version)| (key.clone(), Value::String(version.clone())))
+        .collect();
+    if !versions.is_empty() {
+        companions.insert("prompt_versions".to_string(), Value::Object(versions));
+    }
+    if processed.language_detection != original.language_detection
+        && let Ok(detection) = serde_json::to_value(&processed.language_detection)
+    {
+        companions.insert("language_detection".to_string(), detection);
+    }
+    companions
+}
+
+/// Overwrites the fields of the ticket with the cached values, adding the cached review signals
+/// and prompt versions to those of the ticket
+fn apply_fields(
+    ticket: &ProcessedTicket,
+    cached: &str,
+) -> Result<ProcessedTicket, ProcessingError> {
+    let to_error = |e: serde_json::Error| ProcessingError::UnknownError(e.to_string());
+
+    let mut value = serde_json::to_value(ticket).map_err(to_error)?;
+    let Value::Object(mut fields) = serde_json::from_str(cached).map_err(to_error)? else {
+        return Err(ProcessingError::UnknownError(
+            "Cache entry is not an object".to_string(),
+        ));
+    };
+    let signals = fields.remove("review_signals");
+    let versions = fields.remove("prompt_versions");
+    if let Value::Object(target) = &mut value {
+        target.extend(fields);
+    }
+
+    let mut result: ProcessedTicket = serde_json::from_value(value).map_err(to_error)?;
+    if let Some(signals) = signals {
+        for signal in serde_json::from_value::<Vec<_>>(signals).map_err(to_error)? {
+            result = result.with_review_signal(signal);
+        }
+    }
+    if let Some(versions) = versions {
+        result
+            .prompt_versions
+            .extend(serde_json::from_value::<HashMap<String, String>>(versions).map_err(to_error)?);
+    }
+    Ok(result)
+}
+
+fn all_succeeded(fields: &Map<String, Value>) -> bool {
+    fields.values().all(|value| {
+        matches!(
+            serde_json::from_value::<ProcessingResult<Value>>(value.clone()),
+            Ok(ProcessingResult::Success(_))
+        )
+    })
+}
+
+/// An in-memory cache. Entries expire after `ttl` and the least recently used entry is
+/// evicted once `max_entries` is reached.
+pub struct MemoryCache {
+    ttl: Option<Duration>,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, MemoryEntry>>,
+}
+
+struct MemoryEntry {
+    value: String,
+    inserted: Instant,
+    last_used: Instant,
+}
+
+impl MemoryCache {
+    pub fn new(ttl: Option<Duration>, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = entries
+            .get(key)
+            .is_some_and(|entry| self.ttl.is_some_and(|ttl| entry.inserted.elapsed() > ttl));
+        if expired {
+            entries.remove(key);
+            return None;
+        }
+
+        entries.get_mut(key).map(|entry| {
+            entry.last_used = Instant::now();
+            entry.value.clone()
+        })
+    }
+
+    async fn put(&self, key: &str, value: String) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(key) && entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        let now = Instant::now();
+        entries.insert(
+            key.to_string(),
+            MemoryEntry {
+                value,
+                inserted: now,
+                last_used: now,
+            },
+        );
+    }
+}
+
+/// A cache stored as one JSON file per entry in a directory, so that results survive restarts.
+/// Entries expire `ttl` after they were written and the oldest files are removed once
+/// `max_entries` is reached.
+///
+/// The directory is scanned once on creation; entries written by other instances afterwards
+/// are read but not counted towards `max_entries`.
+pub struct DiskCache {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+    max_entries: usize,
+    /// Entry files, oldest first
+    index: Mutex<VecDeque<PathBuf>>,
+}
+
+impl DiskCache {
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        ttl: Option<Duration>,
+        max_entries: usize,
+    ) -> Result<Self, ProcessingError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            ProcessingError::UnknownError(format!(
+                "Failed to create cache directory {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+        let index = Mutex::new(Self::entries_by_age(&dir).into());
+        Ok(Self {
+            dir,
+            ttl,
+            max_entries,
+            index,
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn entries_by_age(dir: &Path) -> Vec<PathBuf> {
+        let Ok(dir) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut entries: Vec<(SystemTime, PathBuf)> = dir
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let path = entry.path();
+                let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+                path.extension()
+                    .is_some_and(|ext| ext == "json")
+                    .then_some((modified, path))
+            })
+            .collect();
+        entries.sort();
+        entries.into_iter().map(|(_, path)| path).collect()
+    }
+}
+
+#[async_trait]
+impl CacheBackend for DiskCache {
+    async fn get(&self, key: &str) -> Option<String> {
+        let path = self.path(key);
+        if let Some(ttl) = self.ttl {
+            let modified = tokio::fs::metadata(&path).await.ok()?.modified().ok()?;
+            if modified.elapsed().unwrap_or_default() > ttl {
+                self.index.lock().unwrap().retain(|entry| *entry != path);
+                let _ = tokio::fs::remove_file(&path).await;
+                return None;
+            }
+        }
+        tokio::fs::read_to_string(&path).await.ok()
+    }
+
+    async fn put(&self, key: &str, value: String) {
+        let path = self.path(key);
+        let evicted: Vec<PathBuf> = {
+            let mut index = self.index.lock().unwrap();
+            if index.contains(&path) {
+                Vec::new()
+            } else {
+                let excess = (index.len() + 1).saturating_sub(self.max_entries);
+                let evicted = index.drain(..excess).collect();
+                index.push_back(path.clone());
+                evicted
+            }
+        };
+        for old in evicted {
+            let _ = tokio::fs::remove_file(old).await;
+        }
+
+        if let Err(e) = tokio::fs::write(&path, value).await {
+            warn!("Failed to write cache entry {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::{
+        ReviewSignal, SentimentLabel, SentimentScore, SupportTicket, TicketCategory,
+    };
+    use chrono::Utc;
+    use language_enum::Language;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Classifies everything as billing and counts how often it was called
+    struct CountingClassifier {
+        calls: AtomicUsize,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl TicketProcessor for CountingClassifier {
+        async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                ticket.with_category(ProcessingResult::Error(
+                    ProcessingError::ClassificationError("rate limited".to_string()),
+                ))
+            } else {
+                ticket.with_category(ProcessingResult::Success(TicketCategory::Billing))
+            }
+        }
+
+        fn required_fields(&self) -> FieldMask {
+            FieldMask::SENTIMENT
+        }
+
+        fn optional_fields(&self) -> FieldMask {
+            FieldMask::LANGUAGE
+        }
+
+        fn output_fields(&self) -> FieldMask {
+            FieldMask::CATEGORY
+        }
+    }
+
+    fn create_ticket(id: &str, content: &str) -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            id.to_string(),
+            content.to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+        .with_sentiment(ProcessingResult::Success(SentimentScore::new(
+            SentimentLabel::Neutral,
+            0.9,
+        )))
+    }
+
+    fn cached_classifier(fail: bool) -> (Arc<CountingClassifier>, CachedProcessor) {
+        let inner = Arc::new(CountingClassifier {
+            calls: AtomicUsize::new(0),
+            fail,
+        });
+        let cached = CachedProcessor::new(
+            inner.clone(),
+            "classification/test/v1",
+            Arc::new(MemoryCache::new(None, 100)),
+        );
+        (inner, cached)
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_content_hits_cache() {
+        let (inner, cached) = cached_classifier(false);
+
+        let first = cached
+            .process(create_ticket("t1", "I forgot my password"))
+            .await;
+        let second = cached
+            .process(create_ticket("t2", "  i FORGOT my\npassword "))
+            .await;
+
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            second.category,
+            ProcessingResult::Success(TicketCategory::Billing)
+        );
+        assert_eq!(second.ticket.id, "t2");
+        assert_eq!(second.sentiment, first.sentiment);
+        assert_eq!(cached.stats(), CacheStats { hits: 1, misses: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_inputs_are_part_of_the_key() {
+        let (inner, cached) = cached_classifier(false);
+
+        cached
+            .process(create_ticket("t1", "I forgot my password"))
+            .await;
+        let negative = create_ticket("t2", "I forgot my password").with_sentiment(
+            ProcessingResult::Success(SentimentScore::new(SentimentLabel::Negative, 0.9)),
+        );
+        cached.process(negative).await;
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+
+        // The subject and optional fields are read by the processor too
+        let mut with_subject = create_ticket("t3", "I forgot my password");
+        with_subject.ticket = with_subject.ticket.with_subject("Upgrade to the team plan");
+        cached.process(with_subject.clone()).await;
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 3);
+        let german = create_ticket("t4", "I forgot my password")
+            .with_language(ProcessingResult::Success(Language::German));
+        cached.process(german).await;
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 4);
+
+        with_subject.ticket.id = "t5".to_string();
+        cached.process(with_subject).await;
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_not_cached() {
+        let (inner, cached) = cached_classifier(true);
+
+        cached.process(create_ticket("t1", "Hello")).await;
+        cached.process(create_ticket("t2", "Hello")).await;
+
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(cached.stats(), CacheStats { hits: 0, misses: 2 });
+    }
+
+    /// Records a prompt version and a review signal besides the category
+    struct PromptedClassifier;
+
+    #[async_trait]
+    impl TicketProcessor for PromptedClassifier {
+        async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+            ticket
+                .with_category(ProcessingResult::Success(TicketCategory::Billing))
+                .with_prompt_version("classification", "classification-v1")
+                .with_review_signal(ReviewSignal::PossiblePromptInjection {
+                    matches: vec!["role play".to_string()],
+                })
+        }
+
+        fn required_fields(&self) -> FieldMask {
+            FieldMask::empty()
+        }
+
+        fn output_fields(&self) -> FieldMask {
+            FieldMask::CATEGORY
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_hits_keep_review_signals_and_prompt_versions() {
+        let cached = CachedProcessor::new(
+            Arc::new(PromptedClassifier),
+            "classification/test/v1",
+            Arc::new(MemoryCache::new(None, 100)),
+        );
+        let earlier = ReviewSignal::ContentTruncated {
+            processor: "sentiment".to_string(),
+            original_chars: 9000,
+            sent_chars: 4000,
+        };
+
+        let first = cached.process(create_ticket("t1", "Hello")).await;
+        let second = cached
+            .process(
+                create_ticket("t2", "Hello")
+                    .with_prompt_version("summary", "summary-v1")
+                    .with_review_signal(earlier.clone()),
+            )
+            .await;
+
+        assert_eq!(cached.stats(), CacheStats { hits: 1, misses: 1 });
+        assert_eq!(second.review_signals[0], earlier);
+        assert_eq!(second.review_signals[1..], first.review_signals[..]);
+        assert_eq!(
+            second.prompt_versions["classification"],
+            "classification-v1"
+        );
+        assert_eq!(second.prompt_versions["summary"], "summary-v1");
+    }
+
+    #[tokio::test]
+    async fn test_memory_cache_limits() {
+        let cache = MemoryCache::new(None, 2);
+        cache.put("a", "1".to_string()).await;
+        cache.put("b", "2".to_string()).await;
+        cache.get("a").await;
+        cache.put("c", "3".to_string()).await;
+
+        assert_eq!(cache.get("a").await, Some("1".to_string()));
+        assert_eq!(cache.get("b").await, None);
+        assert_eq!(cache.get("c").await, Some("3".to_string()));
+
+        let expiring = MemoryCache::new(Some(Duration::ZERO), 2);
+        expiring.put("a", "1".to_string()).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(expiring.get("a").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache() {
+        let dir = std::env::temp_dir().join(format!("ticket-triage-cache-{}", std::process::id()));
+        let cache = DiskCache::new(&dir, None, 2).unwrap();
+
+        cache.put("a", "1".to_string()).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        cache.put("b", "2".to_string()).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        cache.put("c", "3".to_string()).await;
+
+        assert_eq!(cache.get("a").await, None);
+        assert_eq!(cache.get("b").await, Some("2".to_string()));
+        // A new instance on the same directory sees the stored entries
+        let reopened = DiskCache::new(&dir, None, 2).unwrap();
+        assert_eq!(reopened.get("c").await, Some("3".to_string()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_every_field_mask_flag_names_a_ticket_field() {
+        let ticket = create_ticket("t1", "Hello");
+        let fields = extract_fields(&ticket, FieldMask::all()).unwrap();
+        assert_eq!(fields.len(), FieldMask::all().iter().count());
+    }
+}
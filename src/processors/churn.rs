@@ -0,0 +1,338 @@
+use crate::{
+    pipeline::{FieldMask, TicketProcessor},
+    processors::urgency::phrase_pattern,
+    ticket::{ChurnRisk, ChurnRiskLevel, ContentSource, ProcessedTicket, ProcessingResult},
+    ticket_store::TicketStore,
+};
+use async_trait::async_trait;
+use chrono::Duration;
+use log::info;
+use regex::Regex;
+
+/// Phrases showing the customer intends to leave, in English, Spanish, German and French
+const CANCELLATION_PHRASES: [&str; 17] = [
+    "cancel my subscription",
+    "cancel my account",
+    "close my account",
+    "cancel our contract",
+    "not renewing",
+    "won't renew",
+    "stop using",
+    "cancelar mi suscripción",
+    "dar de baja",
+    "abo kündigen",
+    "abonnement kündigen",
+    "vertrag kündigen",
+    "konto kündigen",
+    "kündigung",
+    "résilier",
+    "résiliation",
+    "me désabonner",
+];
+
+/// Phrases about moving to another service; any configured competitor name counts too
+const SWITCHING_PHRASES: [&str; 12] = [
+    "switch to another provider",
+    "switching to another provider",
+    "switch to a competitor",
+    "switching to a competitor",
+    "switch providers",
+    "moving to another provider",
+    "looking for alternatives",
+    "cambiar de proveedor",
+    "cambiar a otro proveedor",
+    "anbieter wechseln",
+    "zu einem anderen anbieter",
+    "changer de fournisseur",
+];
+
+/// Polarity drop, relative to the customer's earlier tickets, counted as a worsening trend
+const SENTIMENT_DROP: f32 = 0.5;
+
+/// Estimates the risk of the customer leaving from cancellation intent, mentions of
+/// competitors, repeated complaints and how the customer's sentiment develops over their
+/// tickets. Earlier tickets are read from the ticket store the pipeline uses.
+pub struct ChurnRiskProcessor {
+    ticket_store: TicketStore,
+    cancellation: Vec<(String, Regex)>,
+    switching: Vec<(String, Regex)>,
+    competitors: Vec<(String, Regex)>,
+    history_window: Duration,
+    repeated_complaints: usize,
+    content_source: ContentSource,
+}
+
+#[async_trait]
+impl TicketProcessor for ChurnRiskProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "ChurnRiskProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        let history = self.history(&ticket).await;
+        let churn_risk = self.assess(&ticket, &history);
+        let result = ticket.with_churn_risk(ProcessingResult::Success(churn_risk));
+
+        info!(
+            "ChurnRiskProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        match self.content_source {
+            ContentSource::Original => FieldMask::SENTIMENT,
+            ContentSource::Translated => FieldMask::SENTIMENT | FieldMask::TRANSLATION,
+        }
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::CHURN_RISK
+    }
+}
+
+impl ChurnRiskProcessor {
+    /// Reads the customer's earlier tickets from `ticket_store`, which should be the store of
+    /// the pipeline, see `TicketPipeline::with_ticket_store`
+    pub fn new(ticket_store: TicketStore) -> Self {
+        Self {
+            ticket_store,
+            cancellation: phrase_patterns(&CANCELLATION_PHRASES),
+            switching: phrase_patterns(&SWITCHING_PHRASES),
+            competitors: Vec::new(),
+            history_window: Duration::days(30),
+            repeated_complaints: 2,
+            content_source: ContentSource::Original,
+        }
+    }
+
+    /// Names of competitors, matched as whole words ignoring case
+    pub fn with_competitors<S: AsRef<str>>(mut self, competitors: &[S]) -> Self {
+        let competitors: Vec<&str> = competitors.iter().map(|name| name.as_ref()).collect();
+        self.competitors = phrase_patterns(&competitors);
+        self
+    }
+
+    /// How far back earlier tickets of the customer are considered, 30 days by default
+    pub fn with_history_window(mut self, history_window: Duration) -> Self {
+        self.history_window = history_window;
+        self
+    }
+
+    /// Number of earlier negative tickets within the window that count as repeated
+    /// complaints, 2 by default
+    pub fn with_repeated_complaints(mut self, repeated_complaints: usize) -> Self {
+        self.repeated_complaints = repeated_complaints;
+        self
+    }
+
+    /// Whether to look for phrases and competitors in the original text or its English
+    /// translation. The original by default.
+    pub fn with_content_source(mut self, content_source: ContentSource) -> Self {
+        self.content_source = content_source;
+        self
+    }
+
+    /// Earlier tickets of the same customer within the history window, oldest first
+    async fn history(&self, ticket: &ProcessedTicket) -> Vec<ProcessedTicket> {
+        let since = ticket.ticket.timestamp - self.history_window;
+        self.ticket_store
+            .tickets_for_customer(&ticket.ticket.customer_id)
+            .await
+            .into_iter()
+            .filter(|earlier| {
+                earlier.ticket.id != ticket.ticket.id
+                    && earlier.ticket.timestamp >= since
+                    && earlier.ticket.timestamp <= ticket.ticket.timestamp
+            })
+            .collect()
+    }
+
+    fn assess(&self, ticket: &ProcessedTicket, history: &[ProcessedTicket]) -> ChurnRisk {
+        let text = ticket.text(self.content_source);
+        let mut score = 0;
+        let mut evidence = Vec::new();
+
+        if let Some(phrase) = first_match(&self.cancellation, &text) {
+            score += 2;
+            evidence.push(format!("cancellation intent: \"{}\"", phrase));
+        }
+        if let Some(competitor) = first_match(&self.competitors, &text) {
+            score += 1;
+            evidence.push(format!("mentions competitor {}", competitor));
+        } else if let Some(phrase) = first_match(&self.switching, &text) {
+            score += 1;
+            evidence.push(format!("considering a switch: \"{}\"", phrase));
+        }
+
+        let earlier_polarities: Vec<f32> = history.iter().filter_map(polarity).collect();
+        let complaints = earlier_polarities.iter().filter(|p| **p < 0.0).count();
+        if self.repeated_complaints > 0 && complaints >= self.repeated_complaints {
+            score += 1;
+            evidence.push(format!(
+                "{} earlier negative tickets in the last {} days",
+                complaints,
+                self.history_window.num_days()
+            ));
+        }
+
+        if let Some(current) = polarity(ticket)
+            && !earlier_polarities.is_empty()
+        {
+            let earlier_average =
+                earlier_polarities.iter().sum::<f32>() / earlier_polarities.len() as f32;
+            // A customer going from delighted to merely happy is not at risk
+            if current <= 0.0 && earlier_average - current >= SENTIMENT_DROP {
+                score += 1;
+                evidence.push(format!(
+                    "sentiment worsening from {:.2} to {:.2}",
+                    earlier_average, current
+                ));
+            }
+        }
+
+        let level = match score {
+            0 => ChurnRiskLevel::None,
+            1 | 2 => ChurnRiskLevel::Low,
+            _ => ChurnRiskLevel::High,
+        };
+        ChurnRisk { level, evidence }
+    }
+}
+
+fn polarity(ticket: &ProcessedTicket) -> Option<f32> {
+    match &ticket.sentiment {
+        ProcessingResult::Success(sentiment) => Some(sentiment.polarity),
+        _ => None,
+    }
+}
+
+fn phrase_patterns(phrases: &[&str]) -> Vec<(String, Regex)> {
+    phrases
+        .iter()
+        .map(|phrase| phrase.trim())
+        .filter(|phrase| !phrase.is_empty())
+        .map(|phrase| (phrase.to_string(), phrase_pattern(phrase)))
+        .collect()
+}
+
+fn first_match<'a>(patterns: &'a [(String, Regex)], text: &str) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|(_, pattern)| pattern.is_match(text))
+        .map(|(phrase, _)| phrase.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::{SentimentLabel, SentimentScore, SupportTicket};
+    use chrono::{DateTime, Utc};
+
+    fn ticket(id: &str, content: &str, days_ago: i64, label: SentimentLabel) -> ProcessedTicket {
+        let timestamp: DateTime<Utc> = Utc::now() - Duration::days(days_ago);
+        ProcessedTicket::new(SupportTicket::new(
+            id.to_string(),
+            content.to_string(),
+            timestamp,
+            "customer1".to_string(),
+        ))
+        .with_sentiment(ProcessingResult::Success(SentimentScore::new(label, 0.9)))
+    }
+
+    #[tokio::test]
+    async fn test_high_risk_from_history_and_intent() {
+        let store = TicketStore::new();
+        for (id, days_ago) in [("t1", 20), ("t2", 10)] {
+            store
+                .add_ticket(ticket(
+                    id,
+                    "Still broken",
+                    days_ago,
+                    SentimentLabel::Negative,
+                ))
+                .await;
+        }
+        // Too old to count
+        store
+            .add_ticket(ticket("t0", "Broken", 90, SentimentLabel::VeryNegative))
+            .await;
+        let processor = ChurnRiskProcessor::new(store).with_competitors(&["Zendesk"]);
+
+        let processed = processor
+            .process(ticket(
+                "t3",
+                "This is the last straw, I want to cancel my subscription and move to zendesk",
+                0,
+                SentimentLabel::VeryNegative,
+            ))
+            .await;
+
+        assert_eq!(
+            processed.churn_risk,
+            ProcessingResult::Success(ChurnRisk {
+                level: ChurnRiskLevel::High,
+                evidence: vec![
+                    "cancellation intent: \"cancel my subscription\"".to_string(),
+                    "mentions competitor Zendesk".to_string(),
+                    "2 earlier negative tickets in the last 30 days".to_string(),
+                    "sentiment worsening from -0.50 to -1.00".to_string(),
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_low_and_no_risk() {
+        let store = TicketStore::new();
+        store
+            .add_ticket(ticket("t1", "Love it", 5, SentimentLabel::VeryPositive))
+            .await;
+        let processor = ChurnRiskProcessor::new(store);
+
+        let worse = processor
+            .process(ticket(
+                "t2",
+                "The export is slow",
+                0,
+                SentimentLabel::Neutral,
+            ))
+            .await;
+        let content = processor
+            .process(ticket("t3", "Thanks a lot", 0, SentimentLabel::Positive))
+            .await;
+
+        assert!(matches!(
+            worse.churn_risk,
+            ProcessingResult::Success(ChurnRisk {
+                level: ChurnRiskLevel::Low,
+                ..
+            })
+        ));
+        assert_eq!(
+            content.churn_risk,
+            ProcessingResult::Success(ChurnRisk {
+                level: ChurnRiskLevel::None,
+                evidence: vec![],
+            })
+        );
+
+        // Switching and cancelling something other than the service
+        for text in [
+            "How do I switch to the annual plan?",
+            "We are moving to a new office, please update our address",
+            "Wie kann ich die Benachrichtigungen kündigen oder abbestellen?",
+        ] {
+            let processed = processor
+                .process(ticket("t4", text, 0, SentimentLabel::Positive))
+                .await;
+            let ProcessingResult::Success(churn_risk) = processed.churn_risk else {
+                panic!("Expected a churn risk");
+            };
+            assert!(churn_risk.evidence.is_empty(), "{}: {:?}", text, churn_risk);
+        }
+    }
+}
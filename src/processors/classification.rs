@@ -1,19 +1,45 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
 use async_openai::{Client, config::OpenAIConfig, types::*};
+use chrono::Utc;
 use schemars::{JsonSchema, schema_for};
 
 use crate::{
     error::ProcessingError,
-    pipeline::{FieldMask, TicketProcessor},
-    ticket::{ProcessedTicket, ProcessingResult, TicketCategory},
+    llm::LlmConfig,
+    pipeline::{FieldMask, TicketPipeline, TicketProcessor},
+    processors::language::language_name,
+    prompt::{PromptTemplate, delimit_ticket, detect_prompt_injection},
+    ticket::{ProcessedTicket, ProcessingResult, ReviewSignal, TicketCategory},
+    truncation::{TruncatedText, TruncationConfig},
 };
 use async_trait::async_trait;
-use log::info;
+use language_enum::Language;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
 pub struct ClassificationProcessor {
     client: Client<OpenAIConfig>,
+    config: LlmConfig,
+    language_aware: bool,
+    prompt_template: PromptTemplate,
+    truncation: TruncationConfig,
 }
 
+/// The built-in prompt, used unless another template is configured
+const DEFAULT_PROMPT_TEMPLATE: &str = include_str!("../../prompts/classification.prompt");
+
+/// Default limit on the ticket text sent to the model
+const DEFAULT_MAX_TOKENS: usize = 4000;
+
+/// Key under which the prompt version is recorded in `ProcessedTicket::prompt_versions`
+const PROMPT_VERSION_KEY: &str = "classification";
+
 #[async_trait]
 impl TicketProcessor for ClassificationProcessor {
     async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
@@ -23,11 +49,37 @@ impl TicketProcessor for ClassificationProcessor {
         );
 
         let ticket_id = ticket.ticket.id.clone();
-        let category = match self.classify_ticket(&ticket.ticket.content).await {
+        if let Some(reason) = ticket.skip_reason() {
+            info!(
+                "ClassificationProcessor skipped ticket {}: {}",
+                ticket_id, reason
+            );
+            return ticket.with_category(ProcessingResult::Skipped(reason));
+        }
+        let category = match self.classify_ticket(&ticket).await {
             Ok(category) => ProcessingResult::Success(category),
             Err(e) => ProcessingResult::Error(e),
         };
-        let result = ticket.with_category(category);
+        let injection_matches = detect_prompt_injection(&format!(
+            "{}\n{}",
+            ticket.ticket.subject.as_deref().unwrap_or_default(),
+            ticket.ticket.content
+        ));
+        let mut result = ticket
+            .with_category(category)
+            .with_prompt_version(PROMPT_VERSION_KEY, &self.prompt_template.version);
+        if let Some(signal) = self.ticket_text(&result).review_signal("classification") {
+            result = result.with_review_signal(signal);
+        }
+        if !injection_matches.is_empty() {
+            warn!(
+                "Ticket {} looks like a prompt injection attempt: {:?}",
+                ticket_id, injection_matches
+            );
+            result = result.with_review_signal(ReviewSignal::PossiblePromptInjection {
+                matches: injection_matches,
+            });
+        }
 
         info!(
             "ClassificationProcessor finished processing ticket: {}",
@@ -37,7 +89,15 @@ impl TicketProcessor for ClassificationProcessor {
     }
 
     fn required_fields(&self) -> FieldMask {
-        FieldMask::empty()
+        if self.language_aware {
+            FieldMask::LANGUAGE
+        } else {
+            FieldMask::empty()
+        }
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        FieldMask::REDACTION | FieldMask::MESSAGE_KIND
     }
 
     fn output_fields(&self) -> FieldMask {
@@ -47,13 +107,64 @@ impl TicketProcessor for ClassificationProcessor {
 
 impl ClassificationProcessor {
     pub fn new() -> Result<Self, ProcessingError> {
+        Self::with_config(LlmConfig::default())
+    }
+
+    pub fn with_config(config: LlmConfig) -> Result<Self, ProcessingError> {
         Ok(Self {
-            client: Client::new(),
+            client: config.client()?,
+            config,
+            language_aware: false,
+            prompt_template: PromptTemplate::parse(DEFAULT_PROMPT_TEMPLATE)?,
+            truncation: TruncationConfig::default().with_max_tokens(DEFAULT_MAX_TOKENS),
         })
     }
 
-    async fn classify_ticket(&self, text: &str) -> Result<TicketCategory, ProcessingError> {
-        let prompt = self.build_prompt(text);
+    /// Replaces the limits on the ticket text sent to the model. By default quoted replies and
+    /// signatures are removed and the text is limited to about 4000 tokens.
+    pub fn with_truncation(mut self, truncation: TruncationConfig) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    /// Replaces the built-in prompt. The template is sent as the system message and can use
+    /// the variables `language`, `language_note`, `categories` and `examples`. The ticket text
+    /// is never substituted into it; it is sent separately as the user message, delimited by
+    /// `<ticket>` tags.
+    pub fn with_prompt_template(mut self, prompt_template: PromptTemplate) -> Self {
+        self.prompt_template = prompt_template;
+        self
+    }
+
+    /// Waits for language detection and tailors the prompt to the ticket's language: the
+    /// model is told the language and few-shot examples in that language are used when
+    /// available.
+    pub fn with_language_aware_prompts(mut self) -> Self {
+        self.language_aware = true;
+        self
+    }
+
+    async fn classify_ticket(
+        &self,
+        ticket: &ProcessedTicket,
+    ) -> Result<TicketCategory, ProcessingError> {
+        let request = self.build_request(ticket)?;
+
+        let response = self
+            .client
+            .chat()
+            .create(request)
+            .await
+            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+
+        Self::parse_response(&response)
+    }
+
+    fn build_request(
+        &self,
+        ticket: &ProcessedTicket,
+    ) -> Result<CreateChatCompletionRequest, ProcessingError> {
+        let prompt = self.build_prompt(ticket)?;
 
         let schema = schema_for!(OpenAIClassificationResponse);
         let mut response_schema = serde_json::to_value(schema).map_err(|e| {
@@ -63,16 +174,23 @@ impl ClassificationProcessor {
         // Add additionalProperties: false to all object schemas
         Self::add_additional_properties_false(&mut response_schema);
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model("gpt-4.1-nano")
-            .messages(vec![ChatCompletionRequestMessage::User(
-                ChatCompletionRequestUserMessage {
-                    content: ChatCompletionRequestUserMessageContent::Text(prompt),
+        CreateChatCompletionRequestArgs::default()
+            .model(self.config.model.as_str())
+            .messages(vec![
+                ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                    content: ChatCompletionRequestSystemMessageContent::Text(prompt),
                     name: None,
-                },
-            )])
-            .max_tokens(50_u32)
-            .temperature(0.0)
+                }),
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text(delimit_ticket(
+                        ticket.external_subject(),
+                        &self.ticket_text(ticket).text,
+                    )),
+                    name: None,
+                }),
+            ])
+            .max_tokens(self.config.max_tokens)
+            .temperature(self.config.temperature)
             .response_format(ResponseFormat::JsonSchema {
                 json_schema: ResponseFormatJsonSchema {
                     description: None,
@@ -82,15 +200,16 @@ impl ClassificationProcessor {
                 },
             })
             .build()
-            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))
+    }
 
-        let response = self
-            .client
-            .chat()
-            .create(request)
-            .await
-            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+    fn ticket_text(&self, ticket: &ProcessedTicket) -> TruncatedText {
+        self.truncation.apply(ticket.external_content())
+    }
 
+    fn parse_response(
+        response: &CreateChatCompletionResponse,
+    ) -> Result<TicketCategory, ProcessingError> {
         let response: OpenAIClassificationResponse = response
             .choices
             .first()
@@ -105,21 +224,47 @@ impl ClassificationProcessor {
         Ok(response.category)
     }
 
-    fn build_prompt(&self, ticket_content: &str) -> String {
-        format!(
-            r#"Read the customer support message below and classify it into one of the specified categories.
-Output the category and your confidence in the classification as a number between 0.0 and 1.0. Format the result as JSON following the given schema.
-{{"category": "CategoryName", "confidence": 0.95}}
-
-Examples:
-- "My payment failed and I can't access my account" -> {{"category": "Billing", "confidence": 0.95}}
-- "The app crashes when I try to upload" -> {{"category": "Technical", "confidence": 0.90}}
-- "I forgot my password" -> {{"category": "Account", "confidence": 0.85}}
-- "Do you have a mobile app?" -> {{"category": "General", "confidence": 0.80}}
+    fn build_prompt(&self, ticket: &ProcessedTicket) -> Result<String, ProcessingError> {
+        let language = match &ticket.language {
+            ProcessingResult::Success(language) if self.language_aware => Some(language),
+            _ => None,
+        };
+        let language_note = language
+            .map(|language| {
+                format!(
+                    "The ticket is written in {}. Classify it by its meaning in that language, the category names stay in English.\n",
+                    language_name(language)
+                )
+            })
+            .unwrap_or_default();
+        let examples = few_shot_examples(language)
+            .iter()
+            .map(|(text, category, confidence)| {
+                format!(
+                    r#"- "{}" -> {{"category": "{}", "confidence": {:.2}}}"#,
+                    text, category, confidence
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let categories = TicketCategory::ALL
+            .iter()
+            .map(|category| format!("- {:?}: {}", category, category.description()))
+            .collect::<Vec<_>>()
+            .join("\n");
 
-Ticket: "{ticket_content}""#,
-            ticket_content = ticket_content
-        )
+        let values = HashMap::from([
+            (
+                "language",
+                language
+                    .map(language_name)
+                    .unwrap_or_else(|| "unknown".to_string()),
+            ),
+            ("language_note", language_note),
+            ("categories", categories),
+            ("examples", examples),
+        ]);
+        self.prompt_template.render(&values)
     }
 
     /// Recursively adds `additionalProperties: false` to all object schemas in a JSON schema.
@@ -128,17 +273,17 @@ Ticket: "{ticket_content}""#,
     /// `additionalProperties: false` for strict mode to work properly. The schemars crate
     /// doesn't include this property by default, so we need to add it manually to ensure
     /// the OpenAI API accepts our schema.
-    fn add_additional_properties_false(value: &mut serde_json::Value) {
+    pub(crate) fn add_additional_properties_false(value: &mut serde_json::Value) {
         match value {
             serde_json::Value::Object(obj) => {
                 // If this is an object schema (has "type": "object"), add additionalProperties: false
-                if let Some(serde_json::Value::String(type_str)) = obj.get("type") {
-                    if type_str == "object" {
-                        obj.insert(
-                            "additionalProperties".to_string(),
-                            serde_json::Value::Bool(false),
-                        );
-                    }
+                if let Some(serde_json::Value::String(type_str)) = obj.get("type")
+                    && type_str == "object"
+                {
+                    obj.insert(
+                        "additionalProperties".to_string(),
+                        serde_json::Value::Bool(false),
+                    );
                 }
 
                 // Recursively process all nested values
@@ -157,6 +302,420 @@ Ticket: "{ticket_content}""#,
     }
 }
 
+/// Few-shot examples for the prompt, in the ticket's language when available
+fn few_shot_examples(language: Option<&Language>) -> &'static [(&'static str, &'static str, f32)] {
+    match language {
+        Some(Language::Spanish) => &[
+            (
+                "Mi pago falló y no puedo acceder a mi cuenta",
+                "Billing",
+                0.95,
+            ),
+            (
+                "La aplicación se cierra cuando intento subir un archivo",
+                "Technical",
+                0.90,
+            ),
+            ("Olvidé mi contraseña", "Account", 0.85),
+            ("¿Tienen una aplicación móvil?", "General", 0.80),
+        ],
+        Some(Language::German) => &[
+            (
+                "Meine Zahlung ist fehlgeschlagen und ich komme nicht in mein Konto",
+                "Billing",
+                0.95,
+            ),
+            ("Die App stürzt beim Hochladen ab", "Technical", 0.90),
+            ("Ich habe mein Passwort vergessen", "Account", 0.85),
+            ("Gibt es eine mobile App?", "General", 0.80),
+        ],
+        Some(Language::French) => &[
+            (
+                "Mon paiement a échoué et je ne peux pas accéder à mon compte",
+                "Billing",
+                0.95,
+            ),
+            (
+                "L'application plante quand j'essaie de téléverser un fichier",
+                "Technical",
+                0.90,
+            ),
+            ("J'ai oublié mon mot de passe", "Account", 0.85),
+            ("Avez-vous une application mobile ?", "General", 0.80),
+        ],
+        _ => &[
+            (
+                "My payment failed and I can't access my account",
+                "Billing",
+                0.95,
+            ),
+            ("The app crashes when I try to upload", "Technical", 0.90),
+            ("I forgot my password", "Account", 0.85),
+            ("Do you have a mobile app?", "General", 0.80),
+        ],
+    }
+}
+
+/// Settings for classifying tickets in bulk through the OpenAI Batch API
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassificationBatchConfig {
+    /// Directory the JSONL request file is written to before it is uploaded. The file holds
+    /// the ticket text and is deleted once uploaded.
+    pub work_dir: PathBuf,
+    /// How often the batch status is checked while waiting for completion
+    pub poll_interval: Duration,
+    /// How long to wait for the batch to complete before giving up
+    pub timeout: Duration,
+}
+
+impl Default for ClassificationBatchConfig {
+    fn default() -> Self {
+        Self {
+            work_dir: env::temp_dir(),
+            poll_interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Outcome of applying the results of a classification batch to the ticket store
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassificationBatchSummary {
+    pub batch_id: String,
+    pub classified: usize,
+    pub failed: usize,
+}
+
+/// Deletes the batch request file when dropped, so ticket text does not stay on disk whether
+/// or not the upload succeeds
+struct BatchFile<'a>(&'a Path);
+
+impl Drop for BatchFile<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(self.0)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Failed to delete batch file {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Bulk mode: classification requests are written to a JSONL file in the OpenAI batch format,
+/// submitted as a batch, and the results are mapped back onto the stored tickets by
+/// `custom_id`, which is the ticket ID. Much cheaper than synchronous requests but results
+/// can take up to 24 hours.
+impl ClassificationProcessor {
+    /// Submits a batch of tickets in the pipeline's store, waits for it to complete and stores
+    /// the categories on the tickets, which then continue through the pipeline
+    pub async fn classify_in_batch(
+        &self,
+        pipeline: &TicketPipeline,
+        ticket_ids: &[String],
+        batch_config: &ClassificationBatchConfig,
+    ) -> Result<ClassificationBatchSummary, ProcessingError> {
+        let batch_id = self
+            .submit_batch(pipeline, ticket_ids, batch_config)
+            .await?;
+        self.wait_for_batch(&batch_id, batch_config).await?;
+        self.apply_batch_results(pipeline, &batch_id, ticket_ids)
+            .await
+    }
+
+    /// Writes the request file for the tickets in the pipeline's store, uploads it and creates
+    /// the batch. Returns the batch ID. Tickets that are not in the store are left out, and
+    /// tickets that are not classified, such as autoresponders, are marked as skipped and
+    /// announced to the pipeline right away.
+    pub async fn submit_batch(
+        &self,
+        pipeline: &TicketPipeline,
+        ticket_ids: &[String],
+        batch_config: &ClassificationBatchConfig,
+    ) -> Result<String, ProcessingError> {
+        let store = pipeline.ticket_store();
+        let mut lines = Vec::new();
+        for ticket_id in ticket_ids {
+            let Some(ticket) = store.get_ticket(ticket_id).await else {
+                warn!("Ticket {} not found, skipping it in the batch", ticket_id);
+                continue;
+            };
+            if let Some(reason) = ticket.skip_reason() {
+                info!("Skipping ticket {} in the batch: {}", ticket_id, reason);
+                store
+                    .update_ticket(ticket_id, |ticket| {
+                        ticket.category = ProcessingResult::Skipped(reason);
+                    })
+                    .await;
+                pipeline.notify_updated(ticket_id).await;
+                continue;
+            }
+            let body = serde_json::to_value(self.build_request(&ticket)?)
+                .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+            let input = BatchRequestInput {
+                custom_id: ticket_id.clone(),
+                method: BatchRequestInputMethod::POST,
+                url: BatchEndpoint::V1ChatCompletions,
+                body: Some(body),
+            };
+            lines.push(
+                serde_json::to_string(&input)
+                    .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?,
+            );
+        }
+        if lines.is_empty() {
+            return Err(ProcessingError::InvalidTicketData(
+                "No tickets to classify".to_string(),
+            ));
+        }
+
+        let path = batch_config.work_dir.join(format!(
+            "classification-batch-{}.jsonl",
+            Utc::now().format("%Y%m%dT%H%M%S%.3f")
+        ));
+        let batch_file = BatchFile(&path);
+        tokio::fs::write(&path, lines.join("\n") + "\n")
+            .await
+            .map_err(|e| {
+                ProcessingError::ClassificationError(format!(
+                    "Failed to write batch file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        let file = self
+            .client
+            .files()
+            .create(CreateFileRequest {
+                file: FileInput::from(&path),
+                purpose: FilePurpose::Batch,
+                expires_after: None,
+            })
+            .await
+            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+        drop(batch_file);
+
+        let batch = self
+            .client
+            .batches()
+            .create(BatchRequest {
+                input_file_id: file.id,
+                endpoint: BatchEndpoint::V1ChatCompletions,
+                completion_window: BatchCompletionWindow::W24H,
+                metadata: Some(HashMap::from([(
+                    "prompt_version".to_string(),
+                    serde_json::Value::String(self.prompt_template.version.clone()),
+                )])),
+            })
+            .await
+            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+
+        info!(
+            "Submitted classification batch {} with {} tickets",
+            batch.id,
+            lines.len()
+        );
+        Ok(batch.id)
+    }
+
+    /// Polls the batch until it has completed. Fails if the batch failed, expired, was
+    /// cancelled or did not complete within the configured timeout.
+    pub async fn wait_for_batch(
+        &self,
+        batch_id: &str,
+        batch_config: &ClassificationBatchConfig,
+    ) -> Result<Batch, ProcessingError> {
+        let deadline = tokio::time::Instant::now() + batch_config.timeout;
+        loop {
+            let batch = self
+                .client
+                .batches()
+                .retrieve(batch_id)
+                .await
+                .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+
+            match batch.status {
+                BatchStatus::Completed => return Ok(batch),
+                BatchStatus::Failed
+                | BatchStatus::Expired
+                | BatchStatus::Cancelling
+                | BatchStatus::Cancelled => {
+                    return Err(ProcessingError::ClassificationError(format!(
+                        "Batch {} ended with status {:?}",
+                        batch_id, batch.status
+                    )));
+                }
+                BatchStatus::Validating | BatchStatus::InProgress | BatchStatus::Finalizing => {}
+            }
+
+            if tokio::time::Instant::now() + batch_config.poll_interval > deadline {
+                return Err(ProcessingError::ClassificationError(format!(
+                    "Timed out waiting for batch {}",
+                    batch_id
+                )));
+            }
+            tokio::time::sleep(batch_config.poll_interval).await;
+        }
+    }
+
+    /// Downloads the output and error files of a completed batch and stores the category, or
+    /// the error, on each of the submitted `ticket_ids` in the pipeline's store. Submitted
+    /// tickets with a result in neither file are marked as failed. Each updated ticket is
+    /// announced to the pipeline, so processors depending on the category, such as priority
+    /// and routing, run for it when the pipeline is running.
+    pub async fn apply_batch_results(
+        &self,
+        pipeline: &TicketPipeline,
+        batch_id: &str,
+        ticket_ids: &[String],
+    ) -> Result<ClassificationBatchSummary, ProcessingError> {
+        let store = pipeline.ticket_store();
+        let batch = self
+            .client
+            .batches()
+            .retrieve(batch_id)
+            .await
+            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+        // The prompt may have changed since the batch was submitted
+        let prompt_version = batch
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get("prompt_version"))
+            .and_then(|version| version.as_str())
+            .unwrap_or(&self.prompt_template.version)
+            .to_string();
+        // Successful requests are written to the output file and failed ones to the error
+        // file. Either is missing when no request ended that way.
+        let mut lines = Vec::new();
+        for file_id in [batch.output_file_id, batch.error_file_id]
+            .into_iter()
+            .flatten()
+        {
+            let content = self
+                .client
+                .files()
+                .content(&file_id)
+                .await
+                .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+            lines.extend(
+                String::from_utf8_lossy(&content)
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(str::to_string),
+            );
+        }
+
+        let mut summary = ClassificationBatchSummary {
+            batch_id: batch_id.to_string(),
+            classified: 0,
+            failed: 0,
+        };
+        let mut answered = HashSet::new();
+        for line in lines {
+            let output: BatchRequestOutput = serde_json::from_str(&line).map_err(|e| {
+                ProcessingError::ClassificationError(format!("Invalid batch output line: {}", e))
+            })?;
+            let category = match Self::parse_batch_output(&output) {
+                Ok(category) => ProcessingResult::Success(category),
+                Err(e) => ProcessingResult::Error(e),
+            };
+
+            if matches!(category, ProcessingResult::Success(_)) {
+                summary.classified += 1;
+            } else {
+                summary.failed += 1;
+            }
+            if !self
+                .apply_batch_category(pipeline, &output.custom_id, category, &prompt_version)
+                .await
+            {
+                warn!(
+                    "Batch {} returned a result for unknown ticket {}",
+                    batch_id, output.custom_id
+                );
+            }
+            answered.insert(output.custom_id);
+        }
+
+        // Tickets that were left out of the batch are not waiting for a category
+        for ticket_id in ticket_ids.iter().filter(|id| !answered.contains(*id)) {
+            let unanswered = store
+                .get_ticket(ticket_id)
+                .await
+                .is_some_and(|ticket| matches!(ticket.category, ProcessingResult::Processing));
+            if !unanswered {
+                continue;
+            }
+            warn!(
+                "Batch {} returned no result for ticket {}",
+                batch_id, ticket_id
+            );
+            summary.failed += 1;
+            let error = ProcessingError::ClassificationError(format!(
+                "Batch {} returned no result",
+                batch_id
+            ));
+            self.apply_batch_category(
+                pipeline,
+                ticket_id,
+                ProcessingResult::Error(error),
+                &prompt_version,
+            )
+            .await;
+        }
+
+        info!(
+            "Applied classification batch {}: {} classified, {} failed",
+            batch_id, summary.classified, summary.failed
+        );
+        Ok(summary)
+    }
+
+    /// Stores a batch result on the ticket and announces it to the pipeline. Returns false if
+    /// the ticket is not in the store.
+    async fn apply_batch_category(
+        &self,
+        pipeline: &TicketPipeline,
+        ticket_id: &str,
+        category: ProcessingResult<TicketCategory>,
+        prompt_version: &str,
+    ) -> bool {
+        let updated = pipeline
+            .ticket_store()
+            .update_ticket(ticket_id, |ticket| {
+                ticket.category = category;
+                ticket
+                    .prompt_versions
+                    .insert(PROMPT_VERSION_KEY.to_string(), prompt_version.to_string());
+            })
+            .await;
+        if updated.is_some() {
+            pipeline.notify_updated(ticket_id).await;
+        }
+        updated.is_some()
+    }
+
+    fn parse_batch_output(output: &BatchRequestOutput) -> Result<TicketCategory, ProcessingError> {
+        if let Some(error) = &output.error {
+            return Err(ProcessingError::ClassificationError(format!(
+                "{}: {}",
+                error.code, error.message
+            )));
+        }
+        let response = output.response.as_ref().ok_or_else(|| {
+            ProcessingError::ClassificationError("Batch output has no response".to_string())
+        })?;
+        if response.status_code != 200 {
+            return Err(ProcessingError::ClassificationError(format!(
+                "Batch request failed with status {}",
+                response.status_code
+            )));
+        }
+        let response: CreateChatCompletionResponse = serde_json::from_value(response.body.clone())
+            .map_err(|e| ProcessingError::ClassificationError(e.to_string()))?;
+        Self::parse_response(&response)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct OpenAIClassificationResponse {
     category: TicketCategory,
@@ -166,7 +725,488 @@ struct OpenAIClassificationResponse {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{MockServer, chat_completion_response};
+    use crate::ticket::{MessageKind, SupportTicket, TicketPriority};
+    use crate::ticket_store::TicketStore;
     use serde_json::json;
+    use std::sync::Arc;
+
+    fn create_test_ticket(content: &str) -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            "test-1".to_string(),
+            content.to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+    }
+
+    #[test]
+    fn test_language_aware_prompt() {
+        let processor = ClassificationProcessor::new().unwrap();
+        let ticket = create_test_ticket("Olvidé mi contraseña")
+            .with_language(ProcessingResult::Success(Language::Spanish));
+
+        // Without language awareness the language is ignored
+        assert_eq!(processor.required_fields(), FieldMask::empty());
+        let prompt = processor.build_prompt(&ticket).unwrap();
+        assert!(prompt.contains("I forgot my password"));
+        assert!(!prompt.contains("Spanish"));
+
+        let processor = processor.with_language_aware_prompts();
+        assert_eq!(processor.required_fields(), FieldMask::LANGUAGE);
+        let request = processor.build_request(&ticket).unwrap();
+        let ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+            content: ChatCompletionRequestSystemMessageContent::Text(prompt),
+            ..
+        }) = &request.messages[0]
+        else {
+            panic!("Expected a system text message");
+        };
+        assert!(prompt.contains("The ticket is written in Spanish"));
+        assert!(prompt.contains(r#""Olvidé mi contraseña" -> {"category": "Account""#));
+        assert!(!prompt.contains("I forgot my password"));
+
+        // Languages without examples fall back to the English examples
+        let dutch =
+            create_test_ticket("Hallo").with_language(ProcessingResult::Success(Language::Dutch));
+        let prompt = processor.build_prompt(&dutch).unwrap();
+        assert!(prompt.contains("The ticket is written in Dutch"));
+        assert!(prompt.contains("I forgot my password"));
+    }
+
+    #[test]
+    fn test_prompt_template_from_file() {
+        let path = env::temp_dir().join(format!("classification-{}.prompt", std::process::id()));
+        std::fs::write(
+            &path,
+            "version: custom-7\n---\n[{{language}}]\n{{categories}}",
+        )
+        .unwrap();
+        let template = PromptTemplate::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let processor = ClassificationProcessor::new()
+            .unwrap()
+            .with_prompt_template(template);
+        let mut ticket = create_test_ticket("I was charged twice");
+        ticket.ticket = ticket.ticket.with_subject("Refund");
+
+        let prompt = processor.build_prompt(&ticket).unwrap();
+
+        assert!(prompt.starts_with("[unknown]\n- Billing: "));
+        assert!(!prompt.contains("Refund"));
+    }
+
+    #[test]
+    fn test_default_prompt_template() {
+        let template = PromptTemplate::parse(DEFAULT_PROMPT_TEMPLATE).unwrap();
+        assert_eq!(template.version, "classification-v3");
+        assert!(!template.variables().contains(&"content".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_classify_ticket_with_configured_endpoint() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                chat_completion_response(r#"{"category": "Billing", "confidence": 0.9}"#),
+            )
+        })
+        .await;
+        let config = LlmConfig::default()
+            .with_api_base(format!("{}/v1", server.url))
+            .with_api_key("test-key")
+            .with_model("local-model")
+            .with_max_tokens(20);
+        let processor = ClassificationProcessor::with_config(config).unwrap();
+
+        let category = processor
+            .classify_ticket(&create_test_ticket("I was charged twice this month"))
+            .await;
+
+        assert_eq!(category, Ok(TicketCategory::Billing));
+        let processed = processor
+            .process(create_test_ticket("I was charged twice this month"))
+            .await;
+        assert_eq!(
+            processed.prompt_versions[PROMPT_VERSION_KEY],
+            "classification-v3"
+        );
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/v1/chat/completions");
+        let body = requests[0].json();
+        assert_eq!(body["model"], "local-model");
+        assert_eq!(body["max_tokens"], 20);
+    }
+
+    #[tokio::test]
+    async fn test_adversarial_tickets() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                chat_completion_response(r#"{"category": "Other", "confidence": 0.5}"#),
+            )
+        })
+        .await;
+        let processor = ClassificationProcessor::with_config(
+            LlmConfig::default()
+                .with_api_base(server.url.clone())
+                .with_api_key("test-key"),
+        )
+        .unwrap();
+        let tickets: Vec<String> =
+            serde_json::from_str(include_str!("../../testdata/adversarial_tickets.json")).unwrap();
+
+        for content in &tickets {
+            let processed = processor.process(create_test_ticket(content)).await;
+            assert!(
+                processed
+                    .review_signals
+                    .iter()
+                    .any(|signal| matches!(signal, ReviewSignal::PossiblePromptInjection { .. })),
+                "Not flagged: {}",
+                content
+            );
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), tickets.len());
+        for (request, content) in requests.iter().zip(&tickets) {
+            let body = request.json();
+            assert_eq!(body["messages"][0]["role"], "system");
+            assert_eq!(body["messages"][1]["role"], "user");
+            let system = body["messages"][0]["content"].as_str().unwrap();
+            let user = body["messages"][1]["content"].as_str().unwrap();
+            // The customer text only ever appears in the user message, inside one ticket block
+            assert!(!system.contains(content.as_str()));
+            assert!(user.starts_with("<ticket>\n") && user.ends_with("\n</ticket>"));
+            assert_eq!(user.matches("</ticket>").count(), 1);
+        }
+
+        let benign = processor
+            .process(create_test_ticket(
+                "Please ignore my previous email, the invoice is correct after all",
+            ))
+            .await;
+        assert!(benign.review_signals.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_long_ticket_is_truncated() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                chat_completion_response(r#"{"category": "Technical", "confidence": 0.9}"#),
+            )
+        })
+        .await;
+        let processor = ClassificationProcessor::with_config(
+            LlmConfig::default()
+                .with_api_base(server.url.clone())
+                .with_api_key("test-key"),
+        )
+        .unwrap()
+        .with_truncation(TruncationConfig::default().with_max_tokens(50));
+        let content = format!("The sync crashes:\n{}", "at sync::run()\n".repeat(100));
+
+        let processed = processor.process(create_test_ticket(&content)).await;
+
+        let body = server.requests()[0].json();
+        let user = body["messages"][1]["content"].as_str().unwrap();
+        assert!(user.contains("The sync crashes:"));
+        assert!(user.contains("[...]"));
+        assert!(user.len() < 250);
+        assert_eq!(
+            processed.review_signals,
+            vec![ReviewSignal::ContentTruncated {
+                processor: "classification".to_string(),
+                original_chars: content.chars().count(),
+                sent_chars: 200,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skips_messages_that_are_not_genuine() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                chat_completion_response(r#"{"category": "Other", "confidence": 0.9}"#),
+            )
+        })
+        .await;
+        let processor = ClassificationProcessor::with_config(
+            LlmConfig::default()
+                .with_api_base(server.url.clone())
+                .with_api_key("test-key"),
+        )
+        .unwrap();
+
+        let processed = processor
+            .process(
+                create_test_ticket("I am out of the office until Monday")
+                    .with_message_kind(ProcessingResult::Success(MessageKind::OutOfOffice)),
+            )
+            .await;
+
+        assert_eq!(
+            processed.category,
+            ProcessingResult::Skipped("out-of-office message".to_string())
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_classify_ticket_unparseable_response() {
+        let server =
+            MockServer::start(|_| (200, chat_completion_response("I think it is billing"))).await;
+        let config = LlmConfig::default()
+            .with_api_base(server.url.clone())
+            .with_api_key("test-key");
+        let processor = ClassificationProcessor::with_config(config).unwrap();
+
+        let category = processor
+            .classify_ticket(&create_test_ticket("I was charged twice"))
+            .await;
+
+        assert!(matches!(
+            category,
+            Err(ProcessingError::ClassificationError(_))
+        ));
+    }
+
+    /// Stands in for the processors downstream of classification
+    struct AfterCategory;
+
+    #[async_trait]
+    impl TicketProcessor for AfterCategory {
+        async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+            ticket.with_priority(ProcessingResult::Success(TicketPriority::Low))
+        }
+
+        fn required_fields(&self) -> FieldMask {
+            FieldMask::CATEGORY
+        }
+
+        fn output_fields(&self) -> FieldMask {
+            FieldMask::PRIORITY
+        }
+    }
+
+    #[tokio::test]
+    async fn test_classify_in_batch() {
+        let batch = |status: &str| {
+            json!({
+                "id": "batch_1",
+                "object": "batch",
+                "endpoint": "/v1/chat/completions",
+                "input_file_id": "file-in",
+                "completion_window": "24h",
+                "status": status,
+                "output_file_id": (status == "completed").then_some("file-out"),
+                "error_file_id": (status == "completed").then_some("file-err"),
+                "metadata": {"prompt_version": "classification-v1"},
+                "created_at": 0
+            })
+            .to_string()
+        };
+        let output_line = |custom_id: &str, content: &str| {
+            json!({
+                "id": format!("req-{}", custom_id),
+                "custom_id": custom_id,
+                "response": {
+                    "status_code": 200,
+                    "request_id": "r",
+                    "body": serde_json::from_str::<serde_json::Value>(
+                        &chat_completion_response(content)
+                    ).unwrap()
+                },
+                "error": null
+            })
+            .to_string()
+        };
+        let output = [
+            output_line("t1", r#"{"category": "Billing", "confidence": 0.9}"#),
+            output_line("t2", "not json"),
+        ]
+        .join("\n");
+        let errors = json!({
+            "id": "req-t3",
+            "custom_id": "t3",
+            "response": null,
+            "error": {"code": "rate_limit_exceeded", "message": "Too many tokens"}
+        })
+        .to_string();
+
+        let server = MockServer::start(move |request| {
+            match (request.method.as_str(), request.path.as_str()) {
+                ("POST", "/files") => (
+                    200,
+                    json!({
+                        "id": "file-in",
+                        "object": "file",
+                        "bytes": request.body.len(),
+                        "created_at": 0,
+                        "filename": "batch.jsonl",
+                        "purpose": "batch"
+                    })
+                    .to_string(),
+                ),
+                ("POST", "/batches") => (200, batch("validating")),
+                ("GET", "/batches/batch_1") => (200, batch("completed")),
+                ("GET", "/files/file-out/content") => (200, output.clone()),
+                ("GET", "/files/file-err/content") => (200, errors.clone()),
+                _ => (404, "{}".to_string()),
+            }
+        })
+        .await;
+
+        let store = TicketStore::new();
+        let pipeline = TicketPipeline::default()
+            .with_ticket_store(store.clone())
+            .with_processor(Arc::new(AfterCategory));
+        pipeline.run().await.unwrap();
+        for (id, content) in [
+            ("t1", "I was charged twice"),
+            ("t2", "Hello"),
+            ("t3", "My export is empty"),
+            ("t4", "Where is my invoice?"),
+        ] {
+            store
+                .add_ticket(ProcessedTicket::new(SupportTicket::new(
+                    id.to_string(),
+                    content.to_string(),
+                    Utc::now(),
+                    "customer1".to_string(),
+                )))
+                .await;
+        }
+        store
+            .add_ticket(
+                create_test_ticket("I am out of the office until Monday")
+                    .with_message_kind(ProcessingResult::Success(MessageKind::OutOfOffice)),
+            )
+            .await;
+        let processor = ClassificationProcessor::with_config(
+            LlmConfig::default()
+                .with_api_base(server.url.clone())
+                .with_api_key("test-key"),
+        )
+        .unwrap();
+        let work_dir = env::temp_dir().join(format!("classification-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&work_dir).unwrap();
+        let batch_config = ClassificationBatchConfig {
+            work_dir: work_dir.clone(),
+            poll_interval: Duration::from_millis(10),
+            ..ClassificationBatchConfig::default()
+        };
+
+        let summary = processor
+            .classify_in_batch(
+                &pipeline,
+                &["t1", "t2", "t3", "t4", "test-1", "missing"].map(String::from),
+                &batch_config,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            summary,
+            ClassificationBatchSummary {
+                batch_id: "batch_1".to_string(),
+                classified: 1,
+                failed: 3,
+            }
+        );
+        let t1 = store.get_ticket("t1").await.unwrap();
+        assert_eq!(
+            t1.category,
+            ProcessingResult::Success(TicketCategory::Billing)
+        );
+        assert_eq!(t1.prompt_versions[PROMPT_VERSION_KEY], "classification-v1");
+        // Failed requests, from either file, and requests without a result are errors
+        for id in ["t2", "t3", "t4"] {
+            assert!(
+                matches!(
+                    store.get_ticket(id).await.unwrap().category,
+                    ProcessingResult::Error(ProcessingError::ClassificationError(_))
+                ),
+                "{}",
+                id
+            );
+        }
+        assert_eq!(
+            store.get_ticket("test-1").await.unwrap().category,
+            ProcessingResult::Skipped("out-of-office message".to_string())
+        );
+
+        // The uploaded file holds one chat completion request per stored, classifiable ticket
+        let requests = server.requests();
+        let upload = String::from_utf8_lossy(&requests[0].body);
+        assert!(upload.contains(r#""custom_id":"t1""#));
+        assert!(upload.contains(r#""custom_id":"t2""#));
+        assert!(!upload.contains(r#""custom_id":"test-1""#));
+        assert!(!upload.contains(r#""custom_id":"missing""#));
+        assert!(upload.contains(r#""url":"/v1/chat/completions""#));
+        assert_eq!(requests[1].json()["input_file_id"], "file-in");
+
+        // The request file is deleted once uploaded
+        assert_eq!(std::fs::read_dir(&work_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&work_dir).unwrap();
+
+        // The classified, failed and skipped tickets all continue through the pipeline
+        for id in ["t1", "t3", "t4", "test-1"] {
+            for _ in 0..100 {
+                if store.get_ticket(id).await.unwrap().priority != ProcessingResult::Processing {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(
+                store.get_ticket(id).await.unwrap().priority,
+                ProcessingResult::Success(TicketPriority::Low),
+                "{}",
+                id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_failed_batch() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                json!({
+                    "id": "batch_1",
+                    "object": "batch",
+                    "endpoint": "/v1/chat/completions",
+                    "input_file_id": "file-in",
+                    "completion_window": "24h",
+                    "status": "failed",
+                    "created_at": 0
+                })
+                .to_string(),
+            )
+        })
+        .await;
+        let processor = ClassificationProcessor::with_config(
+            LlmConfig::default()
+                .with_api_base(server.url.clone())
+                .with_api_key("test-key"),
+        )
+        .unwrap();
+
+        let result = processor
+            .wait_for_batch("batch_1", &ClassificationBatchConfig::default())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(ProcessingError::ClassificationError(_))
+        ));
+    }
 
     #[test]
     fn test_add_additional_properties_false() {
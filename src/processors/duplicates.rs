@@ -0,0 +1,352 @@
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use crate::{
+    pipeline::{FieldMask, TicketProcessor},
+    ticket::{DuplicateLink, ProcessedTicket, ProcessingResult},
+    ticket_store::TicketStore,
+};
+use async_trait::async_trait;
+use chrono::Duration;
+use log::info;
+
+/// Length of the character shingles texts are compared by
+const SHINGLE_SIZE: usize = 5;
+
+/// Texts shorter than this, after normalization, are too generic to be compared
+const MIN_TEXT_CHARS: usize = 20;
+
+/// Finds earlier tickets with (nearly) the same text, such as the many reports of one outage,
+/// and links the ticket to the cluster they form.
+///
+/// Texts are compared by the Jaccard similarity of their character shingles, which tolerates
+/// small edits, greetings and signatures. Tickets that duplicate a duplicate join the cluster
+/// of its parent, so every cluster has a single parent: its earliest ticket. Tickets are only
+/// linked to tickets before them, ordered by timestamp and then ID, so concurrent reports
+/// cannot link to each other.
+pub struct DuplicateProcessor {
+    ticket_store: TicketStore,
+    threshold: f32,
+    window: Duration,
+}
+
+#[async_trait]
+impl TicketProcessor for DuplicateProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "DuplicateProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        if let Some(reason) = ticket.skip_reason() {
+            info!(
+                "DuplicateProcessor skipped ticket {}: {}",
+                ticket_id, reason
+            );
+            return ticket.with_duplicate(ProcessingResult::Skipped(reason));
+        }
+        let since = ticket.ticket.timestamp - self.window;
+        let recent = self.ticket_store.tickets_since(since).await;
+        let duplicate = self.find_duplicate(&ticket, &recent);
+        let result = ticket.with_duplicate(ProcessingResult::Success(duplicate));
+
+        info!(
+            "DuplicateProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::empty()
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        // Autoresponders and bounces are not compared when the message kind is known
+        FieldMask::MESSAGE_KIND
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::DUPLICATE
+    }
+}
+
+impl DuplicateProcessor {
+    /// Compares tickets against the earlier tickets in `ticket_store`, which should be the
+    /// store of the pipeline, see `TicketPipeline::with_ticket_store`
+    pub fn new(ticket_store: TicketStore) -> Self {
+        Self {
+            ticket_store,
+            threshold: 0.5,
+            window: Duration::hours(24),
+        }
+    }
+
+    /// Minimum similarity, from 0 to 1, for a ticket to count as a duplicate, 0.5 by default
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// How far back earlier tickets are compared, 24 hours by default
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Links the ticket to the cluster of the most similar of the `recent` tickets, if it is
+    /// similar enough. Earlier tickets still being processed are linked the same way first, so
+    /// that a burst of reports joins the cluster of its earliest ticket.
+    pub fn find_duplicate(
+        &self,
+        ticket: &ProcessedTicket,
+        recent: &[ProcessedTicket],
+    ) -> Option<DuplicateLink> {
+        let ticket_shingles = shingles(&ticket_text(ticket))?;
+        // Shingled once, as resolving a burst compares its tickets with the window repeatedly
+        let candidates: Vec<(&ProcessedTicket, HashSet<u64>)> = recent
+            .iter()
+            .filter(|earlier| earlier.skip_reason().is_none())
+            .filter_map(|earlier| Some((earlier, shingles(&ticket_text(earlier))?)))
+            .collect();
+        let (most_similar, similarity) =
+            self.most_similar(ticket, &ticket_shingles, &candidates)?;
+
+        let mut parent = most_similar;
+        let mut visited = HashSet::new();
+        let parent_id = loop {
+            let (earlier, earlier_shingles) = &candidates[parent];
+            match &earlier.duplicate {
+                ProcessingResult::Success(Some(link)) => break link.parent_id.clone(),
+                ProcessingResult::Processing if visited.insert(parent) => {
+                    match self.most_similar(earlier, earlier_shingles, &candidates) {
+                        Some((next, _)) => parent = next,
+                        None => break earlier.ticket.id.clone(),
+                    }
+                }
+                _ => break earlier.ticket.id.clone(),
+            }
+        };
+        let linked = recent
+            .iter()
+            .filter(|earlier| earlier.ticket.id != ticket.ticket.id)
+            .filter(|earlier| match &earlier.duplicate {
+                ProcessingResult::Success(Some(link)) => link.parent_id == parent_id,
+                _ => false,
+            })
+            .count();
+        Some(DuplicateLink {
+            parent_id,
+            similarity,
+            // The parent, its duplicates and this ticket
+            cluster_size: linked + 2,
+        })
+    }
+
+    /// Index of the candidate before the ticket that is most similar to it, and the similarity,
+    /// if any is similar enough
+    fn most_similar(
+        &self,
+        ticket: &ProcessedTicket,
+        ticket_shingles: &HashSet<u64>,
+        candidates: &[(&ProcessedTicket, HashSet<u64>)],
+    ) -> Option<(usize, f32)> {
+        candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, (earlier, _))| {
+                (earlier.ticket.timestamp, &earlier.ticket.id)
+                    < (ticket.ticket.timestamp, &ticket.ticket.id)
+            })
+            .map(|(index, (_, earlier_shingles))| {
+                (index, jaccard(ticket_shingles, earlier_shingles))
+            })
+            .filter(|(_, similarity)| *similarity >= self.threshold)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+}
+
+fn ticket_text(ticket: &ProcessedTicket) -> String {
+    match &ticket.ticket.subject {
+        Some(subject) => format!("{}\n{}", subject, ticket.ticket.content),
+        None => ticket.ticket.content.clone(),
+    }
+}
+
+/// Hashes of the character shingles of the text, ignoring case, punctuation and whitespace
+/// differences. `None` if the text is too short to compare.
+fn shingles(text: &str) -> Option<HashSet<u64>> {
+    let normalized: Vec<char> = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .collect();
+    if normalized.len() < MIN_TEXT_CHARS {
+        return None;
+    }
+    Some(
+        normalized
+            .windows(SHINGLE_SIZE)
+            .map(|shingle| {
+                let mut hasher = DefaultHasher::new();
+                shingle.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect(),
+    )
+}
+
+fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f32 {
+    let intersection = a.intersection(b).count();
+    let union = a.len() + b.len() - intersection;
+    if union == 0 {
+        0.0
+    } else {
+        intersection as f32 / union as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::{MessageKind, SupportTicket};
+    use chrono::Utc;
+
+    fn ticket(id: &str, content: &str, minutes_ago: i64) -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            id.to_string(),
+            content.to_string(),
+            Utc::now() - Duration::minutes(minutes_ago),
+            format!("customer-{}", id),
+        ))
+        .with_message_kind(ProcessingResult::Success(MessageKind::Genuine))
+    }
+
+    #[tokio::test]
+    async fn test_links_duplicates_to_cluster_parent() {
+        let store = TicketStore::new();
+        let processor = DuplicateProcessor::new(store.clone());
+        let reports = [
+            (
+                "t1",
+                "The dashboard is down, I get a 502 Bad Gateway error when I log in.",
+                30,
+            ),
+            (
+                "t2",
+                "Hi! The dashboard is down - I get a 502 bad gateway error when I log in. Thanks, Ann",
+                20,
+            ),
+            (
+                "t3",
+                "the dashboard is down, getting a 502 Bad Gateway error when I log in!!",
+                10,
+            ),
+        ];
+
+        let mut links = Vec::new();
+        for (id, content, minutes_ago) in reports {
+            let processed = processor.process(ticket(id, content, minutes_ago)).await;
+            links.push(processed.duplicate.clone());
+            store.add_ticket(processed).await;
+        }
+
+        assert_eq!(links[0], ProcessingResult::Success(None));
+        let ProcessingResult::Success(Some(second)) = &links[1] else {
+            panic!("Expected a duplicate, got {:?}", links[1]);
+        };
+        assert_eq!(second.parent_id, "t1");
+        assert_eq!(second.cluster_size, 2);
+        let ProcessingResult::Success(Some(third)) = &links[2] else {
+            panic!("Expected a duplicate, got {:?}", links[2]);
+        };
+        assert_eq!(third.parent_id, "t1");
+        assert_eq!(third.cluster_size, 3);
+        assert!(third.similarity > 0.5 && third.similarity <= 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_links_bursts_to_the_earliest_ticket() {
+        let store = TicketStore::new();
+        let processor = DuplicateProcessor::new(store.clone());
+        let report = "The dashboard is down, I get a 502 Bad Gateway error when I log in.";
+        let parent = processor.process(ticket("t1", report, 10)).await;
+        store.add_ticket(parent).await;
+        // Added to the store but not processed yet
+        store
+            .add_ticket(ProcessedTicket::new(SupportTicket::new(
+                "t2".to_string(),
+                format!("{} Thanks, Ann", report),
+                Utc::now() - Duration::minutes(5),
+                "customer-t2".to_string(),
+            )))
+            .await;
+
+        let third = processor
+            .process(ticket("t3", &format!("{} Thanks, Ann!", report), 0))
+            .await;
+
+        let ProcessingResult::Success(Some(link)) = third.duplicate else {
+            panic!("Expected a duplicate, got {:?}", third.duplicate);
+        };
+        assert_eq!(link.parent_id, "t1");
+
+        // Tickets with the same timestamp are ordered by ID
+        let timestamp = Utc::now();
+        let mut a = ticket("a", report, 0);
+        let mut b = ticket("b", report, 0);
+        a.ticket.timestamp = timestamp;
+        b.ticket.timestamp = timestamp;
+        let recent = [a.clone(), b.clone()];
+        assert_eq!(processor.find_duplicate(&a, &recent), None);
+        assert_eq!(
+            processor.find_duplicate(&b, &recent).unwrap().parent_id,
+            "a"
+        );
+
+        // A long burst of reports that are all still being processed
+        let burst: Vec<ProcessedTicket> = (0..100)
+            .map(|i| {
+                let mut report = ticket(&format!("b{:03}", i), report, 0);
+                report.ticket.timestamp = timestamp + Duration::milliseconds(i);
+                report.duplicate = ProcessingResult::Processing;
+                report
+            })
+            .collect();
+        assert_eq!(
+            processor
+                .find_duplicate(&burst[99], &burst)
+                .unwrap()
+                .parent_id,
+            "b000"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ignores_different_old_and_short_tickets() {
+        let store = TicketStore::new();
+        let report = "The dashboard is down, I get a 502 Bad Gateway error when I log in.";
+        store.add_ticket(ticket("old", report, 60 * 48)).await;
+        store
+            .add_ticket(ticket(
+                "other",
+                "How do I change the billing address on my invoices?",
+                5,
+            ))
+            .await;
+        store.add_ticket(ticket("short", "Help!", 5)).await;
+        let processor = DuplicateProcessor::new(store);
+
+        let processed = processor.process(ticket("new", report, 0)).await;
+        let short = processor.process(ticket("short2", "Help!", 0)).await;
+
+        assert_eq!(processed.duplicate, ProcessingResult::Success(None));
+        assert_eq!(short.duplicate, ProcessingResult::Success(None));
+    }
+}
@@ -0,0 +1,585 @@
+use std::sync::Arc;
+
+use crate::{
+    error::ProcessingError,
+    pipeline::{FieldMask, TicketProcessor},
+    processors::priority::get_category_priority_weight,
+    ticket::{ProcessedTicket, ProcessingResult, ReviewSignal, SentimentLabel, SentimentScore},
+};
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::{info, warn};
+
+/// How the results of the ensemble members are combined into a single value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VotingStrategy {
+    /// The value returned by the most members wins, ties go to the higher total confidence
+    MajorityVote,
+    /// For sentiment, the members' score distributions are averaged, weighted by each member's
+    /// confidence, and the most likely label of the average wins. Classification results carry
+    /// no confidence, so for categories this is the same as `MajorityVote`.
+    ConfidenceWeighted,
+    /// The most severe value returned by any member wins
+    HighestSeverity,
+}
+
+/// Runs several processors that produce the same field and combines their results by voting.
+/// Only sentiment and category are supported. When the members disagree a
+/// `ReviewSignal::EnsembleDisagreement` is added to the ticket. The review signals and prompt
+/// versions the members add are kept.
+pub struct EnsembleProcessor {
+    field: FieldMask,
+    strategy: VotingStrategy,
+    members: Vec<Arc<dyn TicketProcessor>>,
+}
+
+#[async_trait]
+impl TicketProcessor for EnsembleProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "EnsembleProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        let outputs = join_all(
+            self.members
+                .iter()
+                .map(|member| member.process(ticket.clone())),
+        )
+        .await;
+        let ticket = with_member_annotations(ticket, &outputs);
+
+        let result = if self.field == FieldMask::SENTIMENT {
+            let scores: Vec<SentimentScore> = outputs
+                .iter()
+                .filter_map(|output| match &output.sentiment {
+                    ProcessingResult::Success(score) => Some(score.clone()),
+                    _ => None,
+                })
+                .collect();
+            let ballots = outputs.into_iter().map(|output| {
+                output.sentiment.map(|score| Ballot {
+                    confidence: score.confidence,
+                    choice: score.label,
+                })
+            });
+            let (sentiment, signal) = self.vote("sentiment", ballots, sentiment_severity);
+            let sentiment = match (self.strategy, weighted_average(&scores)) {
+                (VotingStrategy::ConfidenceWeighted, Some(average)) => sentiment.map(|_| average),
+                _ => sentiment.map(|winner| SentimentScore::new(winner.choice, winner.confidence)),
+            };
+            let ticket = ticket.with_sentiment(sentiment);
+            with_optional_signal(ticket, signal)
+        } else {
+            let ballots = outputs.into_iter().map(|output| {
+                output.category.map(|category| Ballot {
+                    choice: category,
+                    confidence: 1.0, // Classification results do not carry a confidence
+                })
+            });
+            let (category, signal) = self.vote("category", ballots, |category| {
+                get_category_priority_weight(category) as f32
+            });
+            let ticket = ticket.with_category(category.map(|winner| winner.choice));
+            with_optional_signal(ticket, signal)
+        };
+
+        info!(
+            "EnsembleProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        self.members
+            .iter()
+            .fold(FieldMask::empty(), |mask, member| {
+                mask | member.required_fields()
+            })
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        self.members
+            .iter()
+            .fold(FieldMask::empty(), |mask, member| {
+                mask | member.optional_fields()
+            })
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        self.field
+    }
+}
+
+impl EnsembleProcessor {
+    pub fn new(field: FieldMask, strategy: VotingStrategy) -> Result<Self, ProcessingError> {
+        if field != FieldMask::SENTIMENT && field != FieldMask::CATEGORY {
+            return Err(ProcessingError::TicketProcessingError(format!(
+                "Ensembles only support the sentiment or category field, got {:?}",
+                field
+            )));
+        }
+
+        Ok(Self {
+            field,
+            strategy,
+            members: Vec::new(),
+        })
+    }
+
+    pub fn with_member(mut self, processor: Arc<dyn TicketProcessor>) -> Self {
+        self.members.push(processor);
+        self
+    }
+
+    /// Combines the member results for one field. Failed members are ignored unless
+    /// every member failed, in which case the first error is returned. If every member
+    /// skipped the ticket, the result is skipped too.
+    fn vote<T: Clone + PartialEq + std::fmt::Debug>(
+        &self,
+        field_name: &str,
+        results: impl Iterator<Item = ProcessingResult<Ballot<T>>>,
+        severity: impl Fn(&T) -> f32,
+    ) -> (ProcessingResult<Ballot<T>>, Option<ReviewSignal>) {
+        let mut ballots = Vec::new();
+        let mut first_error = None;
+        let mut first_skip = None;
+        for result in results {
+            match result {
+                ProcessingResult::Success(ballot) => ballots.push(ballot),
+                ProcessingResult::Error(err) => {
+                    warn!(
+                        "Ensemble member failed to produce {}: {:?}",
+                        field_name, err
+                    );
+                    first_error.get_or_insert(err);
+                }
+                ProcessingResult::Skipped(reason) => {
+                    first_skip.get_or_insert(reason);
+                }
+                ProcessingResult::Processing => {}
+            }
+        }
+
+        let tallies = tally(&ballots);
+        let Some(winner) = pick_winner(&tallies, self.strategy, severity) else {
+            if first_error.is_none()
+                && let Some(reason) = first_skip
+            {
+                return (ProcessingResult::Skipped(reason), None);
+            }
+            let err = first_error.unwrap_or_else(|| {
+                ProcessingError::TicketProcessingError(format!(
+                    "No ensemble member produced a {}",
+                    field_name
+                ))
+            });
+            return (ProcessingResult::Error(err), None);
+        };
+
+        let confidence = match self.strategy {
+            VotingStrategy::MajorityVote => winner.total_confidence / winner.votes as f32,
+            VotingStrategy::ConfidenceWeighted => {
+                let total: f32 = tallies.iter().map(|t| t.total_confidence).sum();
+                if total > 0.0 {
+                    winner.total_confidence / total
+                } else {
+                    0.0
+                }
+            }
+            VotingStrategy::HighestSeverity => winner.max_confidence,
+        };
+
+        let signal = (tallies.len() > 1).then(|| ReviewSignal::EnsembleDisagreement {
+            field: field_name.to_string(),
+            votes: ballots
+                .iter()
+                .map(|ballot| format!("{:?}", ballot.choice))
+                .collect(),
+            agreement: winner.votes as f32 / ballots.len() as f32,
+        });
+
+        let ballot = Ballot {
+            choice: winner.choice.clone(),
+            confidence,
+        };
+        (ProcessingResult::Success(ballot), signal)
+    }
+}
+
+struct Ballot<T> {
+    choice: T,
+    confidence: f32,
+}
+
+struct Tally<T> {
+    choice: T,
+    votes: usize,
+    total_confidence: f32,
+    max_confidence: f32,
+}
+
+/// Groups ballots by their choice, keeping the order in which choices first appear
+fn tally<T: Clone + PartialEq>(ballots: &[Ballot<T>]) -> Vec<Tally<T>> {
+    let mut tallies: Vec<Tally<T>> = Vec::new();
+    for ballot in ballots {
+        match tallies.iter_mut().find(|t| t.choice == ballot.choice) {
+            Some(tally) => {
+                tally.votes += 1;
+                tally.total_confidence += ballot.confidence;
+                tally.max_confidence = tally.max_confidence.max(ballot.confidence);
+            }
+            None => tallies.push(Tally {
+                choice: ballot.choice.clone(),
+                votes: 1,
+                total_confidence: ballot.confidence,
+                max_confidence: ballot.confidence,
+            }),
+        }
+    }
+    tallies
+}
+
+/// Picks the winning tally. Ties are resolved in favour of the choice seen first.
+fn pick_winner<T>(
+    tallies: &[Tally<T>],
+    strategy: VotingStrategy,
+    severity: impl Fn(&T) -> f32,
+) -> Option<&Tally<T>> {
+    let beats = |candidate: &Tally<T>, best: &Tally<T>| match strategy {
+        VotingStrategy::MajorityVote => {
+            candidate.votes > best.votes
+                || (candidate.votes == best.votes
+                    && candidate.total_confidence > best.total_confidence)
+        }
+        VotingStrategy::ConfidenceWeighted => candidate.total_confidence > best.total_confidence,
+        VotingStrategy::HighestSeverity => severity(&candidate.choice) > severity(&best.choice),
+    };
+
+    tallies.iter().fold(None, |best, candidate| match best {
+        Some(best) if !beats(candidate, best) => Some(best),
+        _ => Some(candidate),
+    })
+}
+
+/// Averages the score distributions, weighting each by its confidence. `None` if there are no
+/// scores or all have zero confidence.
+fn weighted_average(scores: &[SentimentScore]) -> Option<SentimentScore> {
+    let total: f32 = scores.iter().map(|score| score.confidence).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    SentimentScore::from_distribution(
+        scores
+            .iter()
+            .flat_map(|score| {
+                score.distribution.iter().map(|(label, probability)| {
+                    (label.clone(), probability * score.confidence / total)
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Returns how severe a sentiment is, more negative sentiment is more severe
+fn sentiment_severity(label: &SentimentLabel) -> f32 {
+    match label {
+        SentimentLabel::VeryNegative => 4.0,
+        SentimentLabel::Negative => 3.0,
+        SentimentLabel::Neutral => 2.0,
+        SentimentLabel::Positive => 1.0,
+        SentimentLabel::VeryPositive => 0.0,
+    }
+}
+
+/// Adds the review signals and prompt versions the members added, such as a possible prompt
+/// injection flagged by a classifier, to the ticket
+fn with_member_annotations(
+    mut ticket: ProcessedTicket,
+    outputs: &[ProcessedTicket],
+) -> ProcessedTicket {
+    for output in outputs {
+        for signal in &output.review_signals {
+            ticket = ticket.with_review_signal(signal.clone());
+        }
+        ticket
+            .prompt_versions
+            .extend(output.prompt_versions.clone());
+    }
+    ticket
+}
+
+fn with_optional_signal(ticket: ProcessedTicket, signal: Option<ReviewSignal>) -> ProcessedTicket {
+    match signal {
+        Some(signal) => ticket.with_review_signal(signal),
+        None => ticket,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::{SupportTicket, TicketCategory};
+    use chrono::Utc;
+
+    struct FixedSentiment(ProcessingResult<SentimentScore>);
+
+    #[async_trait]
+    impl TicketProcessor for FixedSentiment {
+        async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+            ticket.with_sentiment(self.0.clone())
+        }
+
+        fn required_fields(&self) -> FieldMask {
+            FieldMask::empty()
+        }
+
+        fn output_fields(&self) -> FieldMask {
+            FieldMask::SENTIMENT
+        }
+    }
+
+    struct FixedCategory(TicketCategory);
+
+    #[async_trait]
+    impl TicketProcessor for FixedCategory {
+        async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+            ticket.with_category(ProcessingResult::Success(self.0.clone()))
+        }
+
+        fn required_fields(&self) -> FieldMask {
+            FieldMask::LANGUAGE
+        }
+
+        fn output_fields(&self) -> FieldMask {
+            FieldMask::CATEGORY
+        }
+    }
+
+    /// A classifier that flags every ticket, like `ClassificationProcessor` does for
+    /// suspected prompt injections
+    struct FlaggingCategory;
+
+    #[async_trait]
+    impl TicketProcessor for FlaggingCategory {
+        async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+            ticket
+                .with_category(ProcessingResult::Success(TicketCategory::Sales))
+                .with_prompt_version("classification", "classification-v3")
+                .with_review_signal(ReviewSignal::PossiblePromptInjection {
+                    matches: vec!["forced answer".to_string()],
+                })
+        }
+
+        fn required_fields(&self) -> FieldMask {
+            FieldMask::empty()
+        }
+
+        fn output_fields(&self) -> FieldMask {
+            FieldMask::CATEGORY
+        }
+    }
+
+    fn create_test_ticket() -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            "test-1".to_string(),
+            "Test ticket content".to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+    }
+
+    fn sentiment_ensemble(
+        strategy: VotingStrategy,
+        votes: &[(SentimentLabel, f32)],
+    ) -> EnsembleProcessor {
+        votes.iter().fold(
+            EnsembleProcessor::new(FieldMask::SENTIMENT, strategy).unwrap(),
+            |ensemble, (label, confidence)| {
+                ensemble.with_member(Arc::new(FixedSentiment(ProcessingResult::Success(
+                    SentimentScore::new(label.clone(), *confidence),
+                ))))
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_majority_vote() {
+        let ensemble = sentiment_ensemble(
+            VotingStrategy::MajorityVote,
+            &[
+                (SentimentLabel::Neutral, 0.5),
+                (SentimentLabel::VeryNegative, 0.9),
+                (SentimentLabel::Neutral, 0.75),
+            ],
+        );
+
+        let result = ensemble.process(create_test_ticket()).await;
+
+        assert_eq!(
+            result.sentiment,
+            ProcessingResult::Success(SentimentScore::new(SentimentLabel::Neutral, 0.625))
+        );
+        assert_eq!(
+            result.review_signals,
+            vec![ReviewSignal::EnsembleDisagreement {
+                field: "sentiment".to_string(),
+                votes: vec![
+                    "Neutral".to_string(),
+                    "VeryNegative".to_string(),
+                    "Neutral".to_string()
+                ],
+                agreement: 2.0 / 3.0,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_confidence_weighted() {
+        let ensemble = sentiment_ensemble(
+            VotingStrategy::ConfidenceWeighted,
+            &[
+                (SentimentLabel::Positive, 0.25),
+                (SentimentLabel::Positive, 0.25),
+                (SentimentLabel::Negative, 1.0),
+            ],
+        );
+
+        let result = ensemble.process(create_test_ticket()).await;
+
+        // Positive: 2 * 0.25 * 0.25 / 1.5, negative: 1.0 * 1.0 / 1.5
+        let ProcessingResult::Success(sentiment) = result.sentiment else {
+            panic!("Expected a sentiment, got {:?}", result.sentiment);
+        };
+        assert_eq!(sentiment.label, SentimentLabel::Negative);
+        assert!((sentiment.confidence - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(sentiment.distribution[1].0, SentimentLabel::Positive);
+        assert!((sentiment.distribution[1].1 - 0.125 / 1.5).abs() < 1e-6);
+        // Averaged over the labels: (-0.5 * 2/3 + 0.5 * 1/12) / 0.75
+        assert!((sentiment.polarity + 0.3889).abs() < 1e-3);
+    }
+
+    #[tokio::test]
+    async fn test_highest_severity() {
+        let ensemble = sentiment_ensemble(
+            VotingStrategy::HighestSeverity,
+            &[
+                (SentimentLabel::Positive, 0.9),
+                (SentimentLabel::Negative, 0.4),
+                (SentimentLabel::Neutral, 0.7),
+            ],
+        );
+
+        let result = ensemble.process(create_test_ticket()).await;
+
+        assert_eq!(
+            result.sentiment,
+            ProcessingResult::Success(SentimentScore::new(SentimentLabel::Negative, 0.4))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unanimous_vote_has_no_review_signal() {
+        let ensemble = sentiment_ensemble(
+            VotingStrategy::MajorityVote,
+            &[
+                (SentimentLabel::Negative, 0.8),
+                (SentimentLabel::Negative, 0.6),
+            ],
+        );
+
+        let result = ensemble.process(create_test_ticket()).await;
+
+        assert!(result.review_signals.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_members_are_ignored() {
+        let error = ProcessingError::SentimentAnalysis("backend down".to_string());
+        let ensemble = EnsembleProcessor::new(FieldMask::SENTIMENT, VotingStrategy::MajorityVote)
+            .unwrap()
+            .with_member(Arc::new(FixedSentiment(ProcessingResult::Error(
+                error.clone(),
+            ))))
+            .with_member(Arc::new(FixedSentiment(ProcessingResult::Success(
+                SentimentScore::new(SentimentLabel::Positive, 0.9),
+            ))));
+
+        let result = ensemble.process(create_test_ticket()).await;
+        assert_eq!(
+            result.sentiment,
+            ProcessingResult::Success(SentimentScore::new(SentimentLabel::Positive, 0.9))
+        );
+
+        let failing = EnsembleProcessor::new(FieldMask::SENTIMENT, VotingStrategy::MajorityVote)
+            .unwrap()
+            .with_member(Arc::new(FixedSentiment(ProcessingResult::Error(
+                error.clone(),
+            ))));
+        let result = failing.process(create_test_ticket()).await;
+        assert_eq!(result.sentiment, ProcessingResult::Error(error));
+    }
+
+    #[tokio::test]
+    async fn test_category_highest_severity() {
+        let ensemble = EnsembleProcessor::new(FieldMask::CATEGORY, VotingStrategy::HighestSeverity)
+            .unwrap()
+            .with_member(Arc::new(FixedCategory(TicketCategory::General)))
+            .with_member(Arc::new(FixedCategory(TicketCategory::Technical)))
+            .with_member(Arc::new(FixedCategory(TicketCategory::Billing)));
+
+        assert_eq!(ensemble.required_fields(), FieldMask::LANGUAGE);
+        assert_eq!(ensemble.output_fields(), FieldMask::CATEGORY);
+
+        let result = ensemble.process(create_test_ticket()).await;
+        assert_eq!(
+            result.category,
+            ProcessingResult::Success(TicketCategory::Technical)
+        );
+        assert_eq!(result.sentiment, ProcessingResult::Processing);
+    }
+
+    #[tokio::test]
+    async fn test_member_review_signals_are_kept() {
+        let ensemble = EnsembleProcessor::new(FieldMask::CATEGORY, VotingStrategy::MajorityVote)
+            .unwrap()
+            .with_member(Arc::new(FlaggingCategory))
+            .with_member(Arc::new(FlaggingCategory))
+            .with_member(Arc::new(FixedCategory(TicketCategory::Billing)));
+
+        let result = ensemble.process(create_test_ticket()).await;
+
+        assert_eq!(
+            result.category,
+            ProcessingResult::Success(TicketCategory::Sales)
+        );
+        assert_eq!(
+            result.prompt_versions["classification"],
+            "classification-v3"
+        );
+        assert_eq!(
+            result.review_signals,
+            vec![
+                ReviewSignal::PossiblePromptInjection {
+                    matches: vec!["forced answer".to_string()],
+                },
+                ReviewSignal::EnsembleDisagreement {
+                    field: "category".to_string(),
+                    votes: vec![
+                        "Sales".to_string(),
+                        "Sales".to_string(),
+                        "Billing".to_string()
+                    ],
+                    agreement: 2.0 / 3.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_field() {
+        assert!(EnsembleProcessor::new(FieldMask::PRIORITY, VotingStrategy::MajorityVote).is_err());
+    }
+}
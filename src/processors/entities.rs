@@ -0,0 +1,288 @@
+use std::{path::Path, sync::LazyLock};
+
+use crate::{
+    error::ProcessingError,
+    pipeline::{FieldMask, TicketProcessor},
+    ticket::{ProcessedTicket, ProcessingResult, TicketEntities},
+};
+use async_trait::async_trait;
+use log::info;
+use regex::{Regex, RegexBuilder};
+
+/// Order and invoice IDs recognized unless other patterns are configured
+const DEFAULT_ORDER_ID_PATTERNS: [&str; 2] = [
+    r"(?i)\b(?:ORD|ORDER|INV|INVOICE)[-_#]?\d{4,}\b",
+    r"(?i)\b(?:order|invoice)\s*(?:#|no\.?|number)\s*:?\s*(\d{4,})\b",
+];
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}").unwrap()
+});
+
+static URL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\bhttps?://[^\s<>"'\])]+"#).unwrap());
+
+static VERSION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(?:version\s+|ver\.?\s*|v)(\d+(?:\.\d+)+(?:-[0-9A-Za-z.]+)?)\b|\b(\d+\.\d+\.\d+(?:-[0-9A-Za-z.]+)?)\b")
+        .unwrap()
+});
+
+/// Status codes named together with HTTP, a status or their reason phrase
+static HTTP_STATUS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(?:HTTP(?:/[\d.]+)?|status(?:\s+code)?|error(?:\s+code)?|response(?:\s+code)?)[\s:]*([1-5]\d{2})\b|\b([1-5]\d{2})\s+(?:Bad Request|Unauthorized|Forbidden|Not Found|Method Not Allowed|Conflict|Too Many Requests|Internal Server Error|Bad Gateway|Service Unavailable|Gateway Timeout)\b")
+        .unwrap()
+});
+
+/// Java and JavaScript style traces: the error type followed by an `at` frame
+static STACK_TRACE_AT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?m)([\w.$]*(?:Exception|Error))\b[^\n]*\n\s+at\s+([\w.$<>]+)"#).unwrap()
+});
+
+/// Python tracebacks: the last frame and the error at the end of the traceback
+static PYTHON_TRACEBACK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)Traceback \(most recent call last\):.*File "[^"]+", line \d+, in (\S+).*?\n(\w+(?:Error|Exception))\b"#)
+        .unwrap()
+});
+
+static RUST_PANIC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"thread '[^']*' panicked at ([^\s:]+:\d+)").unwrap());
+
+/// Extracts order IDs, email addresses, URLs, products, versions, stack traces and HTTP status
+/// codes from the ticket. Runs locally, so it reads the original rather than the redacted text.
+pub struct EntityProcessor {
+    order_id_patterns: Vec<Regex>,
+    products: Vec<(String, Regex)>,
+}
+
+#[async_trait]
+impl TicketProcessor for EntityProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "EntityProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        let text = match &ticket.ticket.subject {
+            Some(subject) => format!("{}\n{}", subject, ticket.ticket.content),
+            None => ticket.ticket.content.clone(),
+        };
+        let entities = self.extract(&text);
+        let result = ticket.with_entities(ProcessingResult::Success(entities));
+
+        info!("EntityProcessor finished processing ticket: {}", ticket_id);
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::empty()
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::ENTITIES
+    }
+}
+
+impl Default for EntityProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EntityProcessor {
+    pub fn new() -> Self {
+        Self {
+            order_id_patterns: DEFAULT_ORDER_ID_PATTERNS
+                .iter()
+                .map(|pattern| Regex::new(pattern).unwrap())
+                .collect(),
+            products: Vec::new(),
+        }
+    }
+
+    /// Replaces the order ID patterns. If a pattern has a capture group, the first group is the
+    /// ID, otherwise the whole match is.
+    pub fn with_order_id_patterns<S: AsRef<str>>(
+        mut self,
+        patterns: &[S],
+    ) -> Result<Self, ProcessingError> {
+        self.order_id_patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern.as_ref()).map_err(|e| {
+                    ProcessingError::ConfigError(format!(
+                        "Invalid order ID pattern '{}': {}",
+                        pattern.as_ref(),
+                        e
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// Product names to look for, matched as whole words ignoring case
+    pub fn with_products<S: AsRef<str>>(mut self, products: &[S]) -> Self {
+        self.products = products
+            .iter()
+            .map(|name| name.as_ref().trim())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let pattern = RegexBuilder::new(&format!(r"\b{}\b", regex::escape(name)))
+                    .case_insensitive(true)
+                    .build()
+                    .unwrap();
+                (name.to_string(), pattern)
+            })
+            .collect();
+        self
+    }
+
+    /// Loads the product catalog from a text file with one product name per line. Empty lines
+    /// and lines starting with `#` are skipped.
+    pub fn with_product_catalog(self, path: impl AsRef<Path>) -> Result<Self, ProcessingError> {
+        let path = path.as_ref();
+        let catalog = std::fs::read_to_string(path).map_err(|e| {
+            ProcessingError::ConfigError(format!(
+                "Failed to read product catalog {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let products: Vec<&str> = catalog
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .collect();
+        Ok(self.with_products(&products))
+    }
+
+    pub fn extract(&self, text: &str) -> TicketEntities {
+        let mut entities = TicketEntities::default();
+
+        for pattern in &self.order_id_patterns {
+            for captures in pattern.captures_iter(text) {
+                let id = captures.get(1).or_else(|| captures.get(0)).unwrap();
+                push_unique(&mut entities.order_ids, id.as_str().to_string());
+            }
+        }
+        for email in EMAIL.find_iter(text) {
+            push_unique(&mut entities.emails, email.as_str().to_string());
+        }
+        for url in URL.find_iter(text) {
+            let url = url
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?']);
+            push_unique(&mut entities.urls, url.to_string());
+        }
+        for (name, pattern) in &self.products {
+            if pattern.is_match(text) {
+                push_unique(&mut entities.products, name.clone());
+            }
+        }
+        for captures in VERSION.captures_iter(text) {
+            let version = captures.get(1).or_else(|| captures.get(2)).unwrap();
+            push_unique(&mut entities.versions, version.as_str().to_string());
+        }
+        for captures in HTTP_STATUS.captures_iter(text) {
+            let code = captures.get(1).or_else(|| captures.get(2)).unwrap();
+            if let Ok(code) = code.as_str().parse::<u16>() {
+                push_unique(&mut entities.http_status_codes, code);
+            }
+        }
+        for captures in STACK_TRACE_AT.captures_iter(text) {
+            push_unique(
+                &mut entities.stack_traces,
+                format!("{} at {}", &captures[1], &captures[2]),
+            );
+        }
+        for captures in PYTHON_TRACEBACK.captures_iter(text) {
+            push_unique(
+                &mut entities.stack_traces,
+                format!("{} in {}", &captures[2], &captures[1]),
+            );
+        }
+        for captures in RUST_PANIC.captures_iter(text) {
+            push_unique(
+                &mut entities.stack_traces,
+                format!("panic at {}", &captures[1]),
+            );
+        }
+
+        entities
+    }
+}
+
+fn push_unique<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extracts_entities() {
+        let processor = EntityProcessor::new().with_products(&["Acme Sync", "Cloud Drive"]);
+        let entities = processor.extract(
+            "Order ORD-123456 (invoice number 98765) fails in acme sync v2.4.1 with HTTP 502, \
+             see https://status.example.com/incidents/42. Contact ops@example.com.\n\
+             java.lang.NullPointerException: cart is null\n    at com.acme.Cart.total(Cart.java:42)\n\
+             thread 'main' panicked at src/sync.rs:17:5",
+        );
+
+        assert_eq!(
+            entities,
+            TicketEntities {
+                order_ids: vec!["ORD-123456".to_string(), "98765".to_string()],
+                emails: vec!["ops@example.com".to_string()],
+                urls: vec!["https://status.example.com/incidents/42".to_string()],
+                products: vec!["Acme Sync".to_string()],
+                versions: vec!["2.4.1".to_string()],
+                stack_traces: vec![
+                    "java.lang.NullPointerException at com.acme.Cart.total".to_string(),
+                    "panic at src/sync.rs:17".to_string(),
+                ],
+                http_status_codes: vec![502],
+            }
+        );
+        assert!(entities.has_server_errors());
+    }
+
+    #[test]
+    fn test_python_traceback_and_status_phrases() {
+        let entities = EntityProcessor::new().extract(
+            "Got 404 Not Found, then:\nTraceback (most recent call last):\n  File \"app.py\", line 3, in <module>\n  File \"sync.py\", line 9, in upload\nValueError: bad size",
+        );
+
+        assert_eq!(entities.http_status_codes, vec![404]);
+        assert_eq!(entities.stack_traces, vec!["ValueError in upload"]);
+        assert!(
+            !EntityProcessor::new()
+                .extract("I have 3 cats")
+                .has_server_errors()
+        );
+    }
+
+    #[test]
+    fn test_configured_order_patterns_and_catalog() {
+        let path = std::env::temp_dir().join(format!("catalog-{}.txt", std::process::id()));
+        std::fs::write(&path, "# Products\nWidget Pro\n\nGadget\n").unwrap();
+        let processor = EntityProcessor::new()
+            .with_order_id_patterns(&[r"\bSO(\d{6})\b"])
+            .unwrap()
+            .with_product_catalog(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let entities = processor.extract("My widget pro from SO123456 (ORD-1234) broke");
+
+        assert_eq!(entities.order_ids, vec!["123456"]);
+        assert_eq!(entities.products, vec!["Widget Pro"]);
+        assert!(
+            EntityProcessor::new()
+                .with_order_id_patterns(&["("])
+                .is_err()
+        );
+    }
+}
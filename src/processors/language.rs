@@ -1,14 +1,31 @@
 use crate::{
     error::ProcessingError,
     pipeline::{FieldMask, TicketProcessor},
-    ticket::{ProcessedTicket, ProcessingResult},
+    ticket::{LanguageDetection, LanguageSource, ProcessedTicket, ProcessingResult},
 };
 use async_trait::async_trait;
 use language_enum::Language;
 use log::info;
-use whatlang::{Lang, detect};
+use whatlang::{Detector, Lang};
 
-pub struct LanguageProcessor;
+/// Most alternative languages kept per ticket
+const MAX_ALTERNATIVES: usize = 3;
+
+/// Detects the language of the ticket. When the text is short, the detection unreliable or
+/// the language not supported, the customer's locale or the configured default language is
+/// used instead, if there is one.
+pub struct LanguageProcessor {
+    default_language: Option<Language>,
+    min_chars: usize,
+    filter: LanguageFilter,
+}
+
+/// The languages the detector chooses from
+enum LanguageFilter {
+    All,
+    Allow(Vec<Lang>),
+    Deny(Vec<Lang>),
+}
 
 #[async_trait]
 impl TicketProcessor for LanguageProcessor {
@@ -19,11 +36,33 @@ impl TicketProcessor for LanguageProcessor {
         );
 
         let ticket_id = ticket.ticket.id.clone();
-        let lang = detect(&ticket.ticket.content).map(|info| to_language_enum(info.lang()));
-        let result = ticket.with_language(lang.map_or(
-            ProcessingResult::Error(ProcessingError::LanguageDetectionError()),
-            ProcessingResult::Success,
-        ));
+        let fallback = ticket
+            .ticket
+            .customer_locale
+            .as_deref()
+            .and_then(lang_from_locale)
+            .filter(|lang| self.is_supported(*lang))
+            .map(|lang| (to_language_enum(lang), LanguageSource::CustomerLocale))
+            .or_else(|| {
+                self.default_language
+                    .clone()
+                    .map(|language| (language, LanguageSource::Default))
+            });
+        let detection = self.detect(
+            &ticket.ticket.content,
+            fallback.as_ref().map(|(_, source)| *source),
+        );
+        let language = match (&detection.source, &detection.detected, fallback) {
+            (LanguageSource::Detected, Some(detected), _) => Some(detected.clone()),
+            (_, _, Some((language, _))) => Some(language),
+            _ => None,
+        };
+        let result = ticket
+            .with_language(language.map_or(
+                ProcessingResult::Error(ProcessingError::LanguageDetectionError()),
+                ProcessingResult::Success,
+            ))
+            .with_language_detection(ProcessingResult::Success(detection));
 
         info!(
             "LanguageProcessor finished processing ticket: {}",
@@ -41,6 +80,208 @@ impl TicketProcessor for LanguageProcessor {
     }
 }
 
+impl Default for LanguageProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LanguageProcessor {
+    pub fn new() -> Self {
+        Self {
+            default_language: None,
+            min_chars: 20,
+            filter: LanguageFilter::All,
+        }
+    }
+
+    /// Language of tickets whose language cannot be detected reliably or is not supported,
+    /// and whose customer has no supported locale. Without one, the detected language is
+    /// kept however unreliable.
+    pub fn with_default_language(mut self, default_language: Language) -> Self {
+        self.default_language = Some(default_language);
+        self
+    }
+
+    /// Texts with fewer letters than this are too short to detect their language reliably,
+    /// 20 by default
+    pub fn with_min_chars(mut self, min_chars: usize) -> Self {
+        self.min_chars = min_chars;
+        self
+    }
+
+    /// Only detects these languages, e.g. the languages support is offered in, so that short
+    /// texts are not taken for a similar language such as Latin. Replaces any denylist. Fails
+    /// for languages that cannot be detected.
+    pub fn with_allowlist(mut self, allowlist: &[Language]) -> Result<Self, ProcessingError> {
+        self.filter = LanguageFilter::Allow(to_langs(allowlist)?);
+        Ok(self)
+    }
+
+    /// Never detects these languages. Replaces any allowlist. Fails for languages that cannot
+    /// be detected.
+    pub fn with_denylist(mut self, denylist: &[Language]) -> Result<Self, ProcessingError> {
+        self.filter = LanguageFilter::Deny(to_langs(denylist)?);
+        Ok(self)
+    }
+
+    fn is_supported(&self, lang: Lang) -> bool {
+        match &self.filter {
+            LanguageFilter::All => true,
+            LanguageFilter::Allow(allowlist) => allowlist.contains(&lang),
+            LanguageFilter::Deny(denylist) => !denylist.contains(&lang),
+        }
+    }
+
+    /// Detector for the supported languages except `excluded`
+    fn detector(&self, excluded: &[Lang]) -> Detector {
+        match &self.filter {
+            LanguageFilter::All => Detector::with_denylist(excluded.to_vec()),
+            LanguageFilter::Allow(allowlist) => Detector::with_allowlist(
+                allowlist
+                    .iter()
+                    .filter(|lang| !excluded.contains(lang))
+                    .copied()
+                    .collect(),
+            ),
+            LanguageFilter::Deny(denylist) => {
+                Detector::with_denylist(denylist.iter().chain(excluded).copied().collect())
+            }
+        }
+    }
+
+    /// Detects the language of the text. `fallback` is where the language comes from when the
+    /// detection is not reliable, if there is a fallback language.
+    pub fn detect(&self, text: &str, fallback: Option<LanguageSource>) -> LanguageDetection {
+        // whatlang only chooses among the supported languages for scripts shared by several
+        // languages. Text in a script none of them is written in, e.g. Cyrillic with only
+        // Latin languages allowed, is detected among all languages to tell which it is.
+        let info = self
+            .detector(&[])
+            .detect(text)
+            .or_else(|| Detector::new().detect(text));
+        let supported = info
+            .as_ref()
+            .is_some_and(|info| self.is_supported(info.lang()));
+        let short = text.chars().filter(|c| c.is_alphabetic()).count() < self.min_chars;
+        let reliable = !short && info.as_ref().is_some_and(|info| info.is_reliable());
+        let source = match fallback {
+            Some(source) if !reliable || !supported => source,
+            _ => LanguageSource::Detected,
+        };
+        let Some(info) = info else {
+            return LanguageDetection {
+                detected: None,
+                confidence: 0.0,
+                reliable: false,
+                supported,
+                script: None,
+                alternatives: Vec::new(),
+                source,
+            };
+        };
+
+        // Each runner-up is what the detector picks once the languages ranked above it are
+        // ruled out
+        let mut ranked = vec![info.lang()];
+        while supported && ranked.len() <= MAX_ALTERNATIVES {
+            match self.detector(&ranked).detect_lang(text) {
+                Some(lang) if !ranked.contains(&lang) => ranked.push(lang),
+                _ => break,
+            }
+        }
+        LanguageDetection {
+            detected: Some(to_language_enum(info.lang())),
+            confidence: info.confidence(),
+            reliable,
+            supported,
+            script: Some(info.script().name().to_string()),
+            alternatives: ranked[1..]
+                .iter()
+                .map(|lang| to_language_enum(*lang))
+                .collect(),
+            source,
+        }
+    }
+}
+
+/// Returns the English name of a language, e.g. "Spanish"
+pub fn language_name(language: &Language) -> String {
+    match serde_json::to_value(language) {
+        Ok(serde_json::Value::String(name)) => name,
+        // Languages without a variant serialize as {"Other": "Name"}
+        Ok(serde_json::Value::Object(other)) => other
+            .values()
+            .next()
+            .and_then(|name| name.as_str())
+            .unwrap_or("Unknown")
+            .to_string(),
+        _ => format!("{:?}", language),
+    }
+}
+
+/// Returns the language of a locale such as "es-MX", "pt_BR", "de" or "deu"
+pub fn language_from_locale(locale: &str) -> Option<Language> {
+    lang_from_locale(locale).map(to_language_enum)
+}
+
+/// Returns the whatlang language of a locale
+fn lang_from_locale(locale: &str) -> Option<Lang> {
+    let code = locale
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let lang = match code.as_str() {
+        "en" => Lang::Eng,
+        "es" => Lang::Spa,
+        "fr" => Lang::Fra,
+        "de" => Lang::Deu,
+        "it" => Lang::Ita,
+        "pt" => Lang::Por,
+        "nl" => Lang::Nld,
+        "ru" => Lang::Rus,
+        "uk" => Lang::Ukr,
+        "pl" => Lang::Pol,
+        "cs" => Lang::Ces,
+        "sv" => Lang::Swe,
+        "da" => Lang::Dan,
+        "no" | "nb" => Lang::Nob,
+        "fi" => Lang::Fin,
+        "tr" => Lang::Tur,
+        "el" => Lang::Ell,
+        "he" => Lang::Heb,
+        "ar" => Lang::Ara,
+        "hi" => Lang::Hin,
+        "ja" => Lang::Jpn,
+        "ko" => Lang::Kor,
+        "zh" => Lang::Cmn,
+        "ro" => Lang::Ron,
+        "hu" => Lang::Hun,
+        _ => Lang::from_code(&code)?,
+    };
+    Some(lang)
+}
+
+/// Maps languages onto the whatlang languages detected as them
+fn to_langs(languages: &[Language]) -> Result<Vec<Lang>, ProcessingError> {
+    languages
+        .iter()
+        .map(|language| {
+            Lang::all()
+                .iter()
+                .copied()
+                .find(|lang| to_language_enum(*lang) == *language)
+                .ok_or_else(|| {
+                    ProcessingError::ConfigError(format!(
+                        "{} cannot be detected",
+                        language_name(language)
+                    ))
+                })
+        })
+        .collect()
+}
+
 /// Maps a whatlang Lang enum to a language_enum Language enum.
 /// Covers all 69 languages supported by whatlang with direct mappings
 /// where possible, or uses Language::other() for languages not directly
@@ -132,3 +373,155 @@ fn to_language_enum(lang: Lang) -> Language {
         Lang::Epo => Language::other("Esperanto".to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::SupportTicket;
+    use chrono::Utc;
+
+    #[test]
+    fn test_language_name() {
+        assert_eq!(language_name(&Language::Spanish), "Spanish");
+        assert_eq!(language_name(&to_language_enum(Lang::Epo)), "Esperanto");
+    }
+
+    #[test]
+    fn test_detection_details() {
+        let processor = LanguageProcessor::new();
+
+        let detection = processor.detect(
+            "Hola, desde la última actualización no puedo iniciar sesión en mi cuenta. \
+             ¿Podrían ayudarme a recuperar el acceso lo antes posible?",
+            None,
+        );
+
+        assert_eq!(detection.detected, Some(Language::Spanish));
+        assert!(detection.reliable && detection.confidence > 0.9);
+        assert_eq!(detection.script.as_deref(), Some("Latin"));
+        assert!(!detection.alternatives.is_empty());
+        assert!(!detection.alternatives.contains(&Language::Spanish));
+        assert_eq!(detection.source, LanguageSource::Detected);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_for_short_tickets() {
+        let ticket = |locale: Option<&str>| {
+            let ticket = SupportTicket::new(
+                "t1".to_string(),
+                "ok gracias".to_string(),
+                Utc::now(),
+                "customer1".to_string(),
+            );
+            ProcessedTicket::new(match locale {
+                Some(locale) => ticket.with_customer_locale(locale),
+                None => ticket,
+            })
+        };
+        let processor = LanguageProcessor::new().with_default_language(Language::English);
+
+        let with_locale = processor.process(ticket(Some("es-MX"))).await;
+        let with_default = processor.process(ticket(None)).await;
+        let without_fallback = LanguageProcessor::new().process(ticket(None)).await;
+
+        assert_eq!(
+            with_locale.language,
+            ProcessingResult::Success(Language::Spanish)
+        );
+        let ProcessingResult::Success(detection) = &with_locale.language_detection else {
+            panic!("Expected detection details");
+        };
+        assert!(!detection.reliable);
+        assert_eq!(detection.source, LanguageSource::CustomerLocale);
+        assert_eq!(
+            with_default.language,
+            ProcessingResult::Success(Language::English)
+        );
+        assert!(matches!(
+            with_default.language_detection,
+            ProcessingResult::Success(LanguageDetection {
+                source: LanguageSource::Default,
+                ..
+            })
+        ));
+        assert!(matches!(
+            without_fallback.language_detection,
+            ProcessingResult::Success(LanguageDetection {
+                source: LanguageSource::Detected,
+                ..
+            })
+        ));
+        assert_eq!(language_from_locale("pt_BR"), Some(Language::Portuguese));
+        assert_eq!(language_from_locale("deu"), Some(Language::German));
+        assert_eq!(language_from_locale("xx"), None);
+    }
+
+    #[tokio::test]
+    async fn test_restricts_detection_to_supported_languages() {
+        let supported = [
+            Language::English,
+            Language::Spanish,
+            Language::German,
+            Language::French,
+        ];
+        let processor = LanguageProcessor::new().with_allowlist(&supported).unwrap();
+        let with_default = LanguageProcessor::new()
+            .with_allowlist(&supported)
+            .unwrap()
+            .with_default_language(Language::English);
+        assert!(matches!(
+            LanguageProcessor::new().with_allowlist(&[Language::other("Klingon".to_string())]),
+            Err(ProcessingError::ConfigError(_))
+        ));
+        let ticket = |content: &str| {
+            ProcessedTicket::new(SupportTicket::new(
+                "t1".to_string(),
+                content.to_string(),
+                Utc::now(),
+                "customer1".to_string(),
+            ))
+        };
+
+        // Taken for Portuguese and Catalan among all languages
+        let short = processor.process(ticket("Hola, no puedo entrar")).await;
+        let denied = LanguageProcessor::new()
+            .with_denylist(&[Language::Portuguese, Language::Catalan])
+            .unwrap()
+            .process(ticket("cuenta bloqueada"))
+            .await;
+        let unsupported = with_default
+            .process(ticket("Δεν μπορώ να συνδεθώ στον λογαριασμό μου"))
+            .await;
+        let unsupported_locale = with_default
+            .process(ProcessedTicket::new(
+                SupportTicket::new(
+                    "t2".to_string(),
+                    "ok".to_string(),
+                    Utc::now(),
+                    "customer1".to_string(),
+                )
+                .with_customer_locale("pt-BR"),
+            ))
+            .await;
+
+        assert_eq!(short.language, ProcessingResult::Success(Language::Spanish));
+        assert_eq!(
+            denied.language,
+            ProcessingResult::Success(Language::Spanish)
+        );
+        assert_eq!(
+            unsupported.language,
+            ProcessingResult::Success(Language::English)
+        );
+        let ProcessingResult::Success(detection) = &unsupported.language_detection else {
+            panic!("Expected detection details");
+        };
+        assert_eq!(detection.detected, Some(Language::Greek));
+        assert!(!detection.supported);
+        assert_eq!(detection.source, LanguageSource::Default);
+        assert_eq!(
+            unsupported_locale.language,
+            ProcessingResult::Success(Language::English)
+        );
+    }
+}
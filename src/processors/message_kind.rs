@@ -0,0 +1,200 @@
+use std::sync::LazyLock;
+
+use crate::{
+    pipeline::{FieldMask, TicketProcessor},
+    ticket::{MessageKind, ProcessedTicket, ProcessingResult},
+};
+use async_trait::async_trait;
+use log::info;
+use regex::Regex;
+
+/// Subjects and phrases of bounce messages
+static BOUNCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(undeliverable|undelivered mail|delivery status notification|mail delivery (failed|subsystem)|returned mail|mailer-daemon|could not be delivered|unzustellbar|no se pudo entregar|non remis)\b|\b55\d \d\.\d\.\d\b")
+        .unwrap()
+});
+
+/// Vacation and absence autoresponders. Phrased as the sender being away now, so that
+/// customers writing about an earlier absence ("I was out of the office") are not matched.
+static OUT_OF_OFFICE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(^\s*(out of (the )?office|ooo)\b|\b((i am|i'm|i will be|i'll be) (currently )?(out of (the )?office|away from (the )?office)|(on|from) (vacation|holiday|leave) until|limited access to (my )?e-?mail|abwesenheitsnotiz|ich bin\b[^.!?\n]{0,30}\b(im urlaub|nicht im büro|abwesend)|(estoy|estaré) fuera de la oficina|de vacaciones hasta|je suis absente? du bureau|en congés? jusqu))")
+        .unwrap()
+});
+
+/// Other messages sent by machines
+static AUTO_REPLY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(^\s*(auto(matic)?[ -]?reply|auto:|automatische antwort|respuesta automática|réponse automatique)|\b(this is an automated (message|response)|this mailbox is not monitored|do not reply to this (e-?mail|message)|we have received your (request|message|email) and will)\b)")
+        .unwrap()
+});
+
+/// Phrases of marketing mail that customers rarely use
+static MARKETING_PHRASES: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        r"(?i)\blimited[- ]time offer\b",
+        r"(?i)\b(buy|order|act) now\b",
+        r"(?i)\b\d{2}% off\b",
+        r"(?i)\bseo (services|ranking)\b",
+        r"(?i)\bguest post\b",
+        r"(?i)\b(crypto|bitcoin) (investment|opportunity)\b",
+        r"(?i)\byou('ve| have) (won|been selected)\b",
+        r"(?i)\bfree (gift|consultation)\b",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+});
+
+/// Phrases of marketing mail that also appear in genuine tickets, e.g. a customer who cannot
+/// unsubscribe; they only count together with a marketing phrase
+static COMMON_SPAM_PHRASES: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        r"(?i)\bunsubscribe\b",
+        r"(?i)\bclick here\b",
+        r"(?i)\bfree trial\b",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+});
+
+/// A message needs at least this many spam phrases, one of them a marketing phrase, to count
+/// as spam
+const MIN_SPAM_PHRASES: usize = 3;
+
+/// Classifies inbound messages as genuine or as noise (out-of-office replies, other
+/// autoresponders, bounces, spam and empty messages). Runs locally and should be configured
+/// early: processors that call paid services skip tickets that are not genuine, and
+/// `PriorityProcessor` gives them a low priority.
+#[derive(Default)]
+pub struct MessageKindProcessor;
+
+#[async_trait]
+impl TicketProcessor for MessageKindProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "MessageKindProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        let kind = classify_message(
+            ticket.ticket.subject.as_deref().unwrap_or_default(),
+            &ticket.ticket.content,
+        );
+        let result = ticket.with_message_kind(ProcessingResult::Success(kind));
+
+        info!(
+            "MessageKindProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::empty()
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::MESSAGE_KIND
+    }
+}
+
+impl MessageKindProcessor {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+pub fn classify_message(subject: &str, content: &str) -> MessageKind {
+    let text = format!("{}\n{}", subject, content);
+    if content.chars().filter(|c| c.is_alphanumeric()).count() < 3 {
+        MessageKind::Empty
+    } else if BOUNCE.is_match(&text) {
+        MessageKind::Bounce
+    } else if OUT_OF_OFFICE.is_match(&text) {
+        MessageKind::OutOfOffice
+    } else if AUTO_REPLY.is_match(subject) || AUTO_REPLY.is_match(content) {
+        MessageKind::AutoReply
+    } else if is_spam(&text) {
+        MessageKind::Spam
+    } else {
+        MessageKind::Genuine
+    }
+}
+
+fn is_spam(text: &str) -> bool {
+    let count = |patterns: &[Regex]| {
+        patterns
+            .iter()
+            .filter(|pattern| pattern.is_match(text))
+            .count()
+    };
+    let marketing = count(&MARKETING_PHRASES);
+    marketing > 0 && marketing + count(&COMMON_SPAM_PHRASES) >= MIN_SPAM_PHRASES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_message() {
+        let cases = [
+            ("", " \n-- ", MessageKind::Empty),
+            (
+                "Undeliverable: Your invoice",
+                "Delivery has failed to these recipients",
+                MessageKind::Bounce,
+            ),
+            (
+                "Automatic reply: Ticket #123",
+                "I am out of the office until Monday with limited access to email.",
+                MessageKind::OutOfOffice,
+            ),
+            (
+                "Abwesenheitsnotiz",
+                "Ich bin bis 12.08. im Urlaub.",
+                MessageKind::OutOfOffice,
+            ),
+            (
+                "Re: Ticket #123",
+                "This is an automated message, please do not reply to this email.",
+                MessageKind::AutoReply,
+            ),
+            (
+                "Boost your ranking",
+                "Our SEO services get you to page one. Click here for a free consultation! Unsubscribe",
+                MessageKind::Spam,
+            ),
+            (
+                "Can't log in",
+                "I clicked the reset link but it says the link expired. Please unsubscribe me from the newsletter too.",
+                MessageKind::Genuine,
+            ),
+            (
+                "Charged after cancelling",
+                "Please unsubscribe me from the free trial, I was charged anyway.",
+                MessageKind::Genuine,
+            ),
+            (
+                "Unsubscribe broken",
+                "I click here to unsubscribe and it fails, although my free trial ended.",
+                MessageKind::Genuine,
+            ),
+            (
+                "Rechnung",
+                "Ich war im Urlaub und jetzt ist mein Konto gesperrt.",
+                MessageKind::Genuine,
+            ),
+            (
+                "Missed payment",
+                "I was out of the office and now my account is suspended.",
+                MessageKind::Genuine,
+            ),
+        ];
+
+        for (subject, content, expected) in cases {
+            assert_eq!(classify_message(subject, content), expected, "{}", subject);
+        }
+    }
+}
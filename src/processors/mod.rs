@@ -1,4 +1,16 @@
+pub mod cache;
+pub mod churn;
 pub mod classification;
+pub mod duplicates;
+pub mod ensemble;
+pub mod entities;
 pub mod language;
+pub mod message_kind;
 pub mod priority;
+pub mod redaction;
+pub mod reply_draft;
+pub mod routing;
 pub mod sentiment;
+pub mod summary;
+pub mod translation;
+pub mod urgency;
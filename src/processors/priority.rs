@@ -2,8 +2,9 @@ use crate::{
     error::ProcessingError,
     pipeline::{FieldMask, TicketProcessor},
     ticket::{
-        ProcessedTicket, ProcessingResult, SentimentLabel, SentimentScore, TicketCategory,
-        TicketPriority,
+        ChurnRisk, ChurnRiskLevel, DuplicateLink, MessageKind, ProcessedTicket, ProcessingResult,
+        SentimentLabel, SentimentScore, TicketCategory, TicketEntities, TicketPriority,
+        UrgencyEffect, UrgencySignal,
     },
 };
 use async_trait::async_trait;
@@ -36,6 +37,14 @@ impl TicketProcessor for PriorityProcessor {
         FieldMask::SENTIMENT | FieldMask::CATEGORY
     }
 
+    fn optional_fields(&self) -> FieldMask {
+        FieldMask::ENTITIES
+            | FieldMask::URGENCY
+            | FieldMask::CHURN_RISK
+            | FieldMask::MESSAGE_KIND
+            | FieldMask::DUPLICATE
+    }
+
     fn output_fields(&self) -> FieldMask {
         FieldMask::PRIORITY
     }
@@ -47,14 +56,50 @@ impl PriorityProcessor {
     }
 
     fn calculate_priority(&self, ticket: &ProcessedTicket) -> ProcessingResult<TicketPriority> {
+        let urgency: &[UrgencySignal] = match &ticket.urgency {
+            ProcessingResult::Success(signals) => signals,
+            _ => &[],
+        };
+        let minimum = get_urgency_minimum_priority(urgency);
+
+        // Sentiment and category are skipped for these. Escalation phrases still set a minimum,
+        // as a customer's own message can look like an autoresponder, e.g. "production is down
+        // and I am on vacation until Monday".
+        if let ProcessingResult::Success(kind) = &ticket.message_kind
+            && let Some(priority) = get_message_kind_priority(kind)
+        {
+            return ProcessingResult::Success(
+                minimum.map_or(priority.clone(), |m| m.max(priority)),
+            );
+        }
+
         match (&ticket.sentiment, &ticket.category) {
             (ProcessingResult::Success(sentiment), ProcessingResult::Success(category)) => {
-                let priority = calculate_priority_from_sentiment_and_category(sentiment, category);
-                ProcessingResult::Success(priority)
+                let mut score = calculate_priority_score(sentiment, category);
+                if let ProcessingResult::Success(entities) = &ticket.entities {
+                    score *= get_entity_priority_multiplier(entities);
+                }
+                score *= get_urgency_priority_multiplier(urgency);
+                // A cancellation phrase already boosted the score through its urgency signal
+                let churn_boosted = urgency.iter().any(|signal| signal.kind == "churn");
+                if let ProcessingResult::Success(churn_risk) = &ticket.churn_risk
+                    && !churn_boosted
+                {
+                    score *= get_churn_risk_priority_multiplier(churn_risk);
+                }
+                if let ProcessingResult::Success(Some(duplicate)) = &ticket.duplicate {
+                    score *= get_duplicate_priority_multiplier(duplicate);
+                }
+                let priority = priority_from_score(score);
+                ProcessingResult::Success(minimum.map_or(priority.clone(), |m| m.max(priority)))
             }
-            _ => ProcessingResult::Error(ProcessingError::PriorityCalculationError(
-                "Insufficient data to calculate priority - both sentiment and category are required".to_string(),
-            )),
+            // Escalation phrases set a priority even when sentiment or category failed
+            _ => match minimum {
+                Some(minimum) => ProcessingResult::Success(minimum),
+                None => ProcessingResult::Error(ProcessingError::PriorityCalculationError(
+                    "Insufficient data to calculate priority - both sentiment and category are required".to_string(),
+                )),
+            },
         }
     }
 }
@@ -85,16 +130,108 @@ pub fn get_sentiment_priority_multiplier(sentiment_label: &SentimentLabel) -> f3
     }
 }
 
+/// Returns the priority multiplier for a continuous polarity between -1.0 and 1.0.
+/// Interpolates linearly between the multipliers of the neighbouring sentiment labels.
+pub fn get_polarity_priority_multiplier(polarity: f32) -> f32 {
+    let points = [
+        SentimentLabel::VeryNegative,
+        SentimentLabel::Negative,
+        SentimentLabel::Neutral,
+        SentimentLabel::Positive,
+        SentimentLabel::VeryPositive,
+    ]
+    .map(|label| (label.polarity(), get_sentiment_priority_multiplier(&label)));
+
+    let polarity = polarity.clamp(-1.0, 1.0);
+    points
+        .windows(2)
+        .find(|pair| polarity <= pair[1].0)
+        .map(|pair| {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            y0 + (polarity - x0) / (x1 - x0) * (y1 - y0)
+        })
+        .unwrap_or(points[points.len() - 1].1)
+}
+
+/// Returns the priority multiplier for the entities found in a ticket.
+/// Server errors and crashes affect more than one customer, so they raise priority.
+pub fn get_entity_priority_multiplier(entities: &TicketEntities) -> f32 {
+    if entities.has_server_errors() {
+        1.3
+    } else {
+        1.0
+    }
+}
+
+/// Returns the priority multiplier for the churn risk of the customer
+pub fn get_churn_risk_priority_multiplier(churn_risk: &ChurnRisk) -> f32 {
+    match churn_risk.level {
+        ChurnRiskLevel::High => 1.3,
+        ChurnRiskLevel::Low => 1.1,
+        ChurnRiskLevel::None => 1.0,
+    }
+}
+
+/// Returns the priority multiplier for the cluster of duplicates the ticket belongs to.
+/// Many customers reporting the same issue at once usually means an incident.
+pub fn get_duplicate_priority_multiplier(duplicate: &DuplicateLink) -> f32 {
+    match duplicate.cluster_size {
+        size if size >= 10 => 1.5,
+        size if size >= 3 => 1.2,
+        _ => 1.0,
+    }
+}
+
+/// Returns the fixed priority of messages that are not genuine: spam and empty messages are
+/// ignored, autoresponders and bounces get a low priority
+pub fn get_message_kind_priority(kind: &MessageKind) -> Option<TicketPriority> {
+    match kind {
+        MessageKind::Genuine => None,
+        MessageKind::Spam | MessageKind::Empty => Some(TicketPriority::Ignore),
+        MessageKind::OutOfOffice | MessageKind::AutoReply | MessageKind::Bounce => {
+            Some(TicketPriority::Low)
+        }
+    }
+}
+
+/// Returns the product of the boosts of the urgency signals
+pub fn get_urgency_priority_multiplier(signals: &[UrgencySignal]) -> f32 {
+    signals
+        .iter()
+        .filter_map(|signal| match signal.effect {
+            UrgencyEffect::Boost(boost) => Some(boost),
+            UrgencyEffect::MinimumPriority(_) => None,
+        })
+        .product()
+}
+
+/// Returns the highest minimum priority set by the urgency signals
+pub fn get_urgency_minimum_priority(signals: &[UrgencySignal]) -> Option<TicketPriority> {
+    signals
+        .iter()
+        .filter_map(|signal| match &signal.effect {
+            UrgencyEffect::MinimumPriority(priority) => Some(priority.clone()),
+            UrgencyEffect::Boost(_) => None,
+        })
+        .max()
+}
+
 /// Calculate priority based on sentiment and category using a heuristic
 pub fn calculate_priority_from_sentiment_and_category(
     sentiment: &SentimentScore,
     category: &TicketCategory,
 ) -> TicketPriority {
+    priority_from_score(calculate_priority_score(sentiment, category))
+}
+
+/// Score on a 0-10+ scale from the category weight, adjusted for sentiment
+pub fn calculate_priority_score(sentiment: &SentimentScore, category: &TicketCategory) -> f32 {
     // Base score from category (0-10 scale)
     let category_weight = get_category_priority_weight(category) as f32;
 
-    // Apply sentiment multiplier
-    let sentiment_multiplier = get_sentiment_priority_multiplier(&sentiment.label);
+    // Apply sentiment multiplier, using the expected polarity so that a ticket that is
+    // likely negative but labelled neutral still gets some boost
+    let sentiment_multiplier = get_polarity_priority_multiplier(sentiment.polarity);
 
     // Apply confidence boost for high-confidence negative sentiments
     let confidence_boost = if matches!(
@@ -108,10 +245,12 @@ pub fn calculate_priority_from_sentiment_and_category(
     };
 
     // Calculate final score
-    let final_score = category_weight * sentiment_multiplier * confidence_boost;
+    category_weight * sentiment_multiplier * confidence_boost
+}
 
-    // Map score to priority levels
-    match final_score {
+/// Maps a priority score to a priority level
+pub fn priority_from_score(score: f32) -> TicketPriority {
+    match score {
         s if s >= 10.0 => TicketPriority::Critical, // Very high urgency
         s if s >= 7.0 => TicketPriority::High,      // High urgency
         s if s >= 4.0 => TicketPriority::Medium,    // Medium urgency
@@ -221,4 +360,242 @@ mod tests {
             0.6
         );
     }
+
+    #[test]
+    fn test_polarity_priority_multiplier() {
+        // Matches the label multipliers at the label polarities
+        assert_eq!(get_polarity_priority_multiplier(-1.0), 1.5);
+        assert_eq!(get_polarity_priority_multiplier(-0.5), 1.3);
+        assert_eq!(get_polarity_priority_multiplier(0.0), 1.0);
+        assert_eq!(get_polarity_priority_multiplier(0.5), 0.8);
+        assert_eq!(get_polarity_priority_multiplier(1.0), 0.6);
+
+        // Interpolates in between and clamps outside the range
+        assert!((get_polarity_priority_multiplier(-0.25) - 1.15).abs() < 1e-6);
+        assert_eq!(get_polarity_priority_multiplier(-3.0), 1.5);
+    }
+
+    #[test]
+    fn test_priority_uses_distribution() {
+        // Labelled neutral, but with a lot of weight on the negative labels
+        let sentiment = SentimentScore::from_distribution(vec![
+            (SentimentLabel::Neutral, 0.4),
+            (SentimentLabel::VeryNegative, 0.35),
+            (SentimentLabel::Negative, 0.25),
+        ])
+        .unwrap();
+        let category = TicketCategory::Account;
+
+        let priority = calculate_priority_from_sentiment_and_category(&sentiment, &category);
+        let neutral_priority = calculate_priority_from_sentiment_and_category(
+            &SentimentScore::new(SentimentLabel::Neutral, 0.4),
+            &category,
+        );
+
+        assert_eq!(priority, TicketPriority::High);
+        assert_eq!(neutral_priority, TicketPriority::Medium);
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_raise_priority() {
+        let ticket = ProcessedTicket::new(crate::ticket::SupportTicket::new(
+            "t1".to_string(),
+            "Login returns HTTP 500".to_string(),
+            chrono::Utc::now(),
+            "customer1".to_string(),
+        ))
+        .with_sentiment(ProcessingResult::Success(SentimentScore::new(
+            SentimentLabel::Neutral,
+            0.9,
+        )))
+        .with_category(ProcessingResult::Success(TicketCategory::Account));
+        let processor = PriorityProcessor::new().unwrap();
+
+        let without_entities = processor.process(ticket.clone()).await;
+        let with_entities = processor
+            .process(
+                ticket.with_entities(ProcessingResult::Success(TicketEntities {
+                    http_status_codes: vec![500],
+                    ..TicketEntities::default()
+                })),
+            )
+            .await;
+
+        assert_eq!(
+            without_entities.priority,
+            ProcessingResult::Success(TicketPriority::Medium)
+        );
+        assert_eq!(
+            with_entities.priority,
+            ProcessingResult::Success(TicketPriority::High)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_urgency_signals_escalate_polite_tickets() {
+        let ticket = ProcessedTicket::new(crate::ticket::SupportTicket::new(
+            "t1".to_string(),
+            "Thanks for your help so far! Our production is down since this morning.".to_string(),
+            chrono::Utc::now(),
+            "customer1".to_string(),
+        ))
+        .with_sentiment(ProcessingResult::Success(SentimentScore::new(
+            SentimentLabel::Positive,
+            0.9,
+        )))
+        .with_category(ProcessingResult::Success(TicketCategory::Technical));
+        let outage = UrgencySignal {
+            kind: "outage".to_string(),
+            phrase: "production is down".to_string(),
+            effect: UrgencyEffect::MinimumPriority(TicketPriority::Critical),
+        };
+        let churn = UrgencySignal {
+            kind: "churn".to_string(),
+            phrase: "cancel my subscription".to_string(),
+            effect: UrgencyEffect::Boost(1.3),
+        };
+        let processor = PriorityProcessor::new().unwrap();
+
+        let polite = processor.process(ticket.clone()).await;
+        let escalated = processor
+            .process(
+                ticket
+                    .clone()
+                    .with_urgency(ProcessingResult::Success(vec![outage.clone()])),
+            )
+            .await;
+        let boosted = processor
+            .process(
+                ticket
+                    .clone()
+                    .with_urgency(ProcessingResult::Success(vec![churn.clone()])),
+            )
+            .await;
+        // The churn risk multiplier does not stack with the churn signal
+        let churn_risk = ChurnRisk {
+            level: ChurnRiskLevel::High,
+            evidence: vec![],
+        };
+        let boosted_once = processor
+            .process(
+                ticket
+                    .with_urgency(ProcessingResult::Success(vec![churn]))
+                    .with_churn_risk(ProcessingResult::Success(churn_risk)),
+            )
+            .await;
+
+        assert_eq!(
+            polite.priority,
+            ProcessingResult::Success(TicketPriority::Medium)
+        );
+        assert_eq!(
+            escalated.priority,
+            ProcessingResult::Success(TicketPriority::Critical)
+        );
+        // 8 * 0.8 * 1.3 = 8.32
+        assert_eq!(
+            boosted.priority,
+            ProcessingResult::Success(TicketPriority::High)
+        );
+        // 8 * 0.8 * 1.3 * 1.3 = 10.8 would be Critical
+        assert_eq!(
+            boosted_once.priority,
+            ProcessingResult::Success(TicketPriority::High)
+        );
+
+        // A minimum priority applies even without sentiment
+        let failed_sentiment = ProcessedTicket::new(polite.ticket.clone())
+            .with_sentiment(ProcessingResult::Error(ProcessingError::SentimentAnalysis(
+                "down".to_string(),
+            )))
+            .with_category(ProcessingResult::Success(TicketCategory::Technical))
+            .with_urgency(ProcessingResult::Success(vec![outage]));
+        assert_eq!(
+            processor.process(failed_sentiment).await.priority,
+            ProcessingResult::Success(TicketPriority::Critical)
+        );
+    }
+
+    #[test]
+    fn test_churn_risk_priority_multiplier() {
+        let churn_risk = |level| ChurnRisk {
+            level,
+            evidence: vec![],
+        };
+        assert_eq!(
+            get_churn_risk_priority_multiplier(&churn_risk(ChurnRiskLevel::High)),
+            1.3
+        );
+        assert_eq!(
+            get_churn_risk_priority_multiplier(&churn_risk(ChurnRiskLevel::None)),
+            1.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_messages_that_are_not_genuine_get_low_priority() {
+        let ticket = |kind| {
+            ProcessedTicket::new(crate::ticket::SupportTicket::new(
+                "t1".to_string(),
+                "Out of office, back on Monday".to_string(),
+                chrono::Utc::now(),
+                "customer1".to_string(),
+            ))
+            .with_sentiment(ProcessingResult::Skipped(
+                "out-of-office message".to_string(),
+            ))
+            .with_category(ProcessingResult::Skipped(
+                "out-of-office message".to_string(),
+            ))
+            .with_message_kind(ProcessingResult::Success(kind))
+        };
+        let processor = PriorityProcessor::new().unwrap();
+
+        assert_eq!(
+            processor
+                .process(ticket(MessageKind::OutOfOffice))
+                .await
+                .priority,
+            ProcessingResult::Success(TicketPriority::Low)
+        );
+        assert_eq!(
+            processor.process(ticket(MessageKind::Spam)).await.priority,
+            ProcessingResult::Success(TicketPriority::Ignore)
+        );
+        assert!(matches!(
+            processor
+                .process(ticket(MessageKind::Genuine))
+                .await
+                .priority,
+            ProcessingResult::Error(_)
+        ));
+
+        let outage = UrgencySignal {
+            kind: "outage".to_string(),
+            phrase: "production is down".to_string(),
+            effect: UrgencyEffect::MinimumPriority(TicketPriority::Critical),
+        };
+        assert_eq!(
+            processor
+                .process(
+                    ticket(MessageKind::OutOfOffice)
+                        .with_urgency(ProcessingResult::Success(vec![outage]))
+                )
+                .await
+                .priority,
+            ProcessingResult::Success(TicketPriority::Critical)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_priority_multiplier() {
+        let duplicate = |cluster_size| DuplicateLink {
+            parent_id: "t1".to_string(),
+            similarity: 0.8,
+            cluster_size,
+        };
+        assert_eq!(get_duplicate_priority_multiplier(&duplicate(2)), 1.0);
+        assert_eq!(get_duplicate_priority_multiplier(&duplicate(3)), 1.2);
+        assert_eq!(get_duplicate_priority_multiplier(&duplicate(25)), 1.5);
+    }
 }
@@ -0,0 +1,278 @@
+use std::{collections::BTreeSet, sync::LazyLock};
+
+use crate::{
+    pipeline::{FieldMask, TicketProcessor},
+    ticket::{PiiType, ProcessedTicket, ProcessingResult, RedactedContent},
+};
+use async_trait::async_trait;
+use log::info;
+use regex::{Captures, Regex};
+
+/// Finds personal data in the ticket subject and content and replaces it with placeholders
+/// such as `[EMAIL]`, producing the redacted view that processors calling external services
+/// send instead of the original text.
+///
+/// Detection is regex based. Card numbers and IBANs are only redacted when their checksum is
+/// valid, so order numbers and other long digit runs are left alone.
+#[derive(Default)]
+pub struct RedactionProcessor;
+
+#[async_trait]
+impl TicketProcessor for RedactionProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "RedactionProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        let mut pii_types = BTreeSet::new();
+        let subject = ticket
+            .ticket
+            .subject
+            .as_deref()
+            .map(|subject| redact(subject, &mut pii_types));
+        let content = redact(&ticket.ticket.content, &mut pii_types);
+        let result = ticket.with_redaction(ProcessingResult::Success(RedactedContent {
+            subject,
+            content,
+            pii_types: pii_types.into_iter().collect(),
+        }));
+
+        info!(
+            "RedactionProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::empty()
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::REDACTION
+    }
+}
+
+impl RedactionProcessor {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+struct PiiPattern {
+    pii_type: PiiType,
+    placeholder: &'static str,
+    /// Must contain a `pii` group, anything around it is kept
+    pattern: Regex,
+    is_valid: fn(&str) -> bool,
+}
+
+/// Applied in order, so card numbers and IBANs are replaced before the phone number pattern
+/// can match parts of them
+static PII_PATTERNS: LazyLock<Vec<PiiPattern>> = LazyLock::new(|| {
+    vec![
+        PiiPattern {
+            pii_type: PiiType::Email,
+            placeholder: "[EMAIL]",
+            pattern: Regex::new(r"(?P<pii>[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,})").unwrap(),
+            is_valid: |_| true,
+        },
+        PiiPattern {
+            pii_type: PiiType::CreditCard,
+            placeholder: "[CREDIT_CARD]",
+            pattern: Regex::new(r"\b(?P<pii>\d(?:[ -]?\d){12,18})\b").unwrap(),
+            is_valid: passes_luhn,
+        },
+        PiiPattern {
+            pii_type: PiiType::Iban,
+            placeholder: "[IBAN]",
+            pattern: Regex::new(r"\b(?P<pii>[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){2,7}(?: ?[A-Z0-9]{1,3})?)\b")
+                .unwrap(),
+            is_valid: is_valid_iban,
+        },
+        PiiPattern {
+            pii_type: PiiType::PhoneNumber,
+            placeholder: "[PHONE]",
+            // The leading character keeps IDs such as ORD-1234-5678 or #1234-5678-90 from
+            // matching
+            pattern: Regex::new(
+                r"(?:^|[^\w#-])(?P<pii>(?:\+\d{1,3}[ .-]?)?(?:\(\d{1,5}\)[ .-]?)?\d{2,8}(?:[ .-]\d{2,8}){0,4})\b",
+            )
+            .unwrap(),
+            is_valid: is_plausible_phone_number,
+        },
+        PiiPattern {
+            pii_type: PiiType::PostalAddress,
+            placeholder: "[ADDRESS]",
+            pattern: Regex::new(concat!(
+                // 221 Baker Street
+                r"\b(?P<pii>\d{1,5}(?:\s+[A-Z][A-Za-z]*\.?){1,4}\s+(?:Street|St|Avenue|Ave|Road|Rd|Boulevard|Blvd|Lane|Ln|Drive|Dr|Court|Ct|Way|Place|Pl|Terrace)\b\.?",
+                // Hauptstraße 5
+                r"|[A-ZÄÖÜ][\wäöüß-]*(?:straße|strasse|str\.|weg|gasse|platz|allee)\s+\d{1,4}[a-z]?\b",
+                // Calle de Alcalá, 45
+                r"|(?:Calle|Avenida|Avda\.|Paseo|Plaza)(?:\s+[\p{L}'-]+){1,4},?\s+\d{1,4}\b",
+                // 12 rue de la Paix
+                r"|\d{1,4},?\s+(?i:rue|avenue|boulevard|chemin|allée|impasse)(?:\s+[\p{L}'-]+){1,4})",
+            ))
+            .unwrap(),
+            is_valid: |_| true,
+        },
+    ]
+});
+
+/// Replaces the personal data in the text, adding the kinds found to `pii_types`
+pub fn redact(text: &str, pii_types: &mut BTreeSet<PiiType>) -> String {
+    let mut result = text.to_string();
+    for pii in PII_PATTERNS.iter() {
+        result = pii
+            .pattern
+            .replace_all(&result, |captures: &Captures| {
+                let whole = captures.get(0).unwrap();
+                let found = captures.name("pii").unwrap();
+                if !(pii.is_valid)(found.as_str()) {
+                    return whole.as_str().to_string();
+                }
+                pii_types.insert(pii.pii_type);
+                format!(
+                    "{}{}{}",
+                    &whole.as_str()[..found.start() - whole.start()],
+                    pii.placeholder,
+                    &whole.as_str()[found.end() - whole.start()..]
+                )
+            })
+            .into_owned();
+    }
+    result
+}
+
+fn passes_luhn(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2, digit * 2) {
+            (1, doubled) if doubled > 9 => doubled - 9,
+            (1, doubled) => doubled,
+            _ => digit,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Checks the ISO 13616 mod-97 checksum
+fn is_valid_iban(iban: &str) -> bool {
+    let iban: String = iban.chars().filter(|c| !c.is_whitespace()).collect();
+    if !(15..=34).contains(&iban.len()) {
+        return false;
+    }
+    let rearranged = iban[4..].chars().chain(iban[..4].chars());
+    let mut remainder = 0u32;
+    for c in rearranged {
+        let Some(value) = c.to_digit(36) else {
+            return false;
+        };
+        let step = if value < 10 { 10 } else { 100 };
+        remainder = (remainder * step + value) % 97;
+    }
+    remainder == 1
+}
+
+static DATE_OR_IP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:\d{4}[-./]\d{1,2}[-./]\d{1,2}|\d{1,2}[-./]\d{1,2}[-./]\d{2,4}|\d{1,3}(?:\.\d{1,3}){3})$")
+        .unwrap()
+});
+
+/// Phone numbers have 7 to 15 digits and are either written with a country code or split
+/// into groups; plain digit runs are more likely to be IDs
+fn is_plausible_phone_number(candidate: &str) -> bool {
+    let digits = candidate.chars().filter(|c| c.is_ascii_digit()).count();
+    let grouped = candidate.starts_with('+') || candidate.contains([' ', '-', '.', '(']);
+    (7..=15).contains(&digits) && grouped && !DATE_OR_IP.is_match(candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::SupportTicket;
+    use chrono::Utc;
+
+    fn redact_text(text: &str) -> (String, Vec<PiiType>) {
+        let mut pii_types = BTreeSet::new();
+        let redacted = redact(text, &mut pii_types);
+        (redacted, pii_types.into_iter().collect())
+    }
+
+    #[test]
+    fn test_redacts_pii() {
+        let (redacted, pii_types) = redact_text(
+            "I'm Ann (ann.smith@example.co.uk, +44 20 7946 0958). Card 4111 1111 1111 1111 was charged, \
+             refund to DE89 3704 0044 0532 0130 00. Ship to 221 Baker Street or Hauptstraße 5.",
+        );
+
+        assert_eq!(
+            redacted,
+            "I'm Ann ([EMAIL], [PHONE]). Card [CREDIT_CARD] was charged, \
+             refund to [IBAN]. Ship to [ADDRESS] or [ADDRESS]."
+        );
+        assert_eq!(
+            pii_types,
+            vec![
+                PiiType::Email,
+                PiiType::PhoneNumber,
+                PiiType::CreditCard,
+                PiiType::Iban,
+                PiiType::PostalAddress
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keeps_numbers_that_are_not_pii() {
+        let text = "Order ORD-1234-5678 and invoice 4111111111111112 from 2024-03-15, \
+                    server 192.168.10.20 on version 2.14.3, error 503, order #1234-5678-90, \
+                    ref A1234-5678-90";
+        assert_eq!(redact_text(text), (text.to_string(), vec![]));
+
+        assert!(passes_luhn("4111-1111-1111-1111"));
+        assert!(!passes_luhn("1234"));
+        assert!(is_valid_iban("GB82WEST12345698765432"));
+        assert!(!is_valid_iban("GB82WEST12345698765431"));
+    }
+
+    #[tokio::test]
+    async fn test_process_records_redacted_view() {
+        let ticket = SupportTicket::new(
+            "t1".to_string(),
+            "Please call me on (030) 1234 5678".to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        )
+        .with_subject("Refund for bob@example.com");
+
+        let processed = RedactionProcessor::new()
+            .process(ProcessedTicket::new(ticket))
+            .await;
+
+        assert_eq!(processed.external_content(), "Please call me on [PHONE]");
+        assert_eq!(processed.external_subject(), Some("Refund for [EMAIL]"));
+        assert_eq!(
+            processed.redaction,
+            ProcessingResult::Success(RedactedContent {
+                subject: Some("Refund for [EMAIL]".to_string()),
+                content: "Please call me on [PHONE]".to_string(),
+                pii_types: vec![PiiType::Email, PiiType::PhoneNumber],
+            })
+        );
+        // The original text is kept for local processors
+        assert_eq!(
+            processed.ticket.content,
+            "Please call me on (030) 1234 5678"
+        );
+    }
+}
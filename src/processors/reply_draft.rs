@@ -0,0 +1,506 @@
+use std::{collections::HashMap, path::Path};
+
+use async_openai::{Client, config::OpenAIConfig, types::*};
+
+use crate::{
+    error::ProcessingError,
+    llm::LlmConfig,
+    pipeline::{FieldMask, TicketProcessor},
+    processors::language::language_name,
+    prompt::{PromptTemplate, delimit_ticket},
+    ticket::{ProcessedTicket, ProcessingResult, ReplyDraft, TicketCategory},
+    truncation::TruncationConfig,
+};
+use async_trait::async_trait;
+use language_enum::Language;
+use log::{info, warn};
+
+/// The built-in prompt for polishing drafts
+const DEFAULT_POLISH_TEMPLATE: &str = include_str!("../../prompts/reply_polish.prompt");
+
+/// Key under which the polish prompt version is recorded in `ProcessedTicket::prompt_versions`
+const PROMPT_VERSION_KEY: &str = "reply_polish";
+
+/// Variables filled from extracted entities
+const ENTITY_VARIABLES: [&str; 6] = [
+    "order_id",
+    "order_ids",
+    "product",
+    "version",
+    "url",
+    "http_status",
+];
+
+/// A reply with `{{variable}}` placeholders for one kind of ticket. Stored as a file in the
+/// `PromptTemplate` format, whose header may name the `category` and `language` the reply is
+/// for; a template without them fits tickets of any category or language.
+///
+/// ```text
+/// version: 1
+/// category: Billing
+/// language: English
+/// ---
+/// Thank you for contacting us about order {{order_id}}. ...
+/// ```
+///
+/// Available variables: `ticket_id`, `customer_id`, `customer_tier`, `subject`, `category`,
+/// `language`, and from entity extraction `order_id`, `order_ids`, `product`, `version`,
+/// `url` and `http_status`. A template only fits a ticket that has values for all the
+/// variables it uses.
+#[derive(Debug, Clone)]
+pub struct ReplyTemplate {
+    pub name: String,
+    pub category: Option<TicketCategory>,
+    pub language: Option<Language>,
+    pub template: PromptTemplate,
+}
+
+impl ReplyTemplate {
+    pub fn parse(name: &str, text: &str) -> Result<Self, ProcessingError> {
+        let template = PromptTemplate::parse(text)?;
+        Ok(Self {
+            name: name.to_string(),
+            category: header_value(&template, "category", name)?,
+            language: header_value(&template, "language", name)?,
+            template,
+        })
+    }
+
+    /// Scores how well the template fits: a matching category counts 0.5, a matching
+    /// language 0.3 and using entities of the ticket 0.2. `None` if the category or language
+    /// differ or a variable has no value.
+    fn score(&self, ticket: &ProcessedTicket, values: &HashMap<&str, String>) -> Option<f32> {
+        let variables = self.template.variables();
+        if variables
+            .iter()
+            .any(|name| !values.contains_key(name.as_str()))
+        {
+            return None;
+        }
+        let mut score = 0.0;
+        if let Some(category) = &self.category {
+            match &ticket.category {
+                ProcessingResult::Success(ticket_category) if ticket_category == category => {
+                    score += 0.5
+                }
+                _ => return None,
+            }
+        }
+        if let Some(language) = &self.language {
+            match &ticket.language {
+                ProcessingResult::Success(ticket_language) if ticket_language == language => {
+                    score += 0.3
+                }
+                _ => return None,
+            }
+        }
+        if variables
+            .iter()
+            .any(|name| ENTITY_VARIABLES.contains(&name.as_str()))
+        {
+            score += 0.2;
+        }
+        Some(score)
+    }
+}
+
+/// Parses a header value such as `category: Billing` into the enum it names
+fn header_value<T: serde::de::DeserializeOwned>(
+    template: &PromptTemplate,
+    key: &str,
+    name: &str,
+) -> Result<Option<T>, ProcessingError> {
+    template
+        .metadata
+        .get(key)
+        .map(|value| {
+            serde_json::from_value(serde_json::Value::String(value.clone())).map_err(|_| {
+                ProcessingError::ConfigError(format!(
+                    "Invalid {} '{}' in reply template {}",
+                    key, value, name
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Drafts a reply from the best fitting template of a local library, filled in with the
+/// customer's and the ticket's details. Optionally an LLM rewords the draft to fit the
+/// customer's message; the draft is kept as it is if that fails.
+pub struct ReplyDraftProcessor {
+    templates: Vec<ReplyTemplate>,
+    min_score: f32,
+    polish: Option<ReplyPolisher>,
+}
+
+struct ReplyPolisher {
+    client: Client<OpenAIConfig>,
+    config: LlmConfig,
+    prompt_template: PromptTemplate,
+    truncation: TruncationConfig,
+}
+
+#[async_trait]
+impl TicketProcessor for ReplyDraftProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "ReplyDraftProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        if let Some(reason) = ticket.skip_reason() {
+            info!(
+                "ReplyDraftProcessor skipped ticket {}: {}",
+                ticket_id, reason
+            );
+            return ticket.with_reply_draft(ProcessingResult::Skipped(reason));
+        }
+        let mut draft = self.draft(&ticket);
+        let mut polished_with = None;
+        if let (Some(polisher), Some(draft)) = (&self.polish, draft.as_mut()) {
+            match polisher.polish(&ticket, &draft.text).await {
+                Ok(text) => {
+                    draft.text = text;
+                    draft.polished = true;
+                    polished_with = Some(&polisher.prompt_template.version);
+                }
+                Err(e) => warn!(
+                    "Failed to polish the reply draft for ticket {}: {}",
+                    ticket_id, e
+                ),
+            }
+        }
+        let mut result = ticket.with_reply_draft(ProcessingResult::Success(draft));
+        if let Some(version) = polished_with {
+            result = result.with_prompt_version(PROMPT_VERSION_KEY, version);
+        }
+
+        info!(
+            "ReplyDraftProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::LANGUAGE | FieldMask::CATEGORY
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        // The redacted content is used when drafts are polished
+        FieldMask::ENTITIES | FieldMask::MESSAGE_KIND | FieldMask::REDACTION
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::REPLY_DRAFT
+    }
+}
+
+impl ReplyDraftProcessor {
+    pub fn new(templates: Vec<ReplyTemplate>) -> Self {
+        Self {
+            templates,
+            min_score: 0.5,
+            polish: None,
+        }
+    }
+
+    /// Loads the templates from the `.reply` files in the directory, named after the files
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self, ProcessingError> {
+        let dir = dir.as_ref();
+        let read_error = |e: std::io::Error| {
+            ProcessingError::ConfigError(format!(
+                "Failed to read reply templates {}: {}",
+                dir.display(),
+                e
+            ))
+        };
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()
+            .map_err(read_error)?;
+        paths.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "reply")
+        });
+        paths.sort();
+
+        let templates = paths
+            .iter()
+            .map(|path| {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                let text = std::fs::read_to_string(path).map_err(read_error)?;
+                ReplyTemplate::parse(&name, &text)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(templates))
+    }
+
+    /// Minimum score for a template to be used, 0.5 by default, so templates must at least
+    /// match the ticket's category
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = min_score;
+        self
+    }
+
+    /// Has the model reword the drafts. The redacted ticket text is sent along with the draft,
+    /// and the `subject` variable is filled from the redacted subject, as the filled draft is
+    /// sent too. Templates using `customer_id` send the customer ID as it is.
+    pub fn with_llm_polish(mut self, config: LlmConfig) -> Result<Self, ProcessingError> {
+        self.polish = Some(ReplyPolisher {
+            client: config.client()?,
+            config,
+            prompt_template: PromptTemplate::parse(DEFAULT_POLISH_TEMPLATE)?,
+            truncation: TruncationConfig::default().with_max_tokens(2000),
+        });
+        Ok(self)
+    }
+
+    /// Fills in the best fitting template; the first one wins among equally good templates
+    pub fn draft(&self, ticket: &ProcessedTicket) -> Option<ReplyDraft> {
+        let values = template_values(ticket, self.polish.is_some());
+        let mut best: Option<(&ReplyTemplate, f32)> = None;
+        for template in &self.templates {
+            if let Some(score) = template.score(ticket, &values)
+                && score >= self.min_score
+                && best.is_none_or(|(_, best_score)| score > best_score)
+            {
+                best = Some((template, score));
+            }
+        }
+        let (template, score) = best?;
+        // Every variable has a value, checked when scoring
+        let text = template.template.render(&values).ok()?;
+        Some(ReplyDraft {
+            template: template.name.clone(),
+            text: text.trim().to_string(),
+            score,
+            polished: false,
+        })
+    }
+}
+
+impl ReplyPolisher {
+    async fn polish(
+        &self,
+        ticket: &ProcessedTicket,
+        draft: &str,
+    ) -> Result<String, ProcessingError> {
+        let language = match &ticket.language {
+            ProcessingResult::Success(language) => language_name(language),
+            _ => "the language of the draft".to_string(),
+        };
+        let mut values = HashMap::new();
+        values.insert("language", language);
+        let prompt = self.prompt_template.render(&values)?;
+        let ticket_text = self.truncation.apply(ticket.external_content());
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(self.config.model.as_str())
+            .messages(vec![
+                ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                    content: ChatCompletionRequestSystemMessageContent::Text(prompt),
+                    name: None,
+                }),
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text(format!(
+                        "{}\n<draft>\n{}\n</draft>",
+                        delimit_ticket(ticket.external_subject(), &ticket_text.text),
+                        draft
+                    )),
+                    name: None,
+                }),
+            ])
+            .max_tokens(self.config.max_tokens)
+            .temperature(self.config.temperature)
+            .build()
+            .map_err(|e| ProcessingError::ReplyDraftError(e.to_string()))?;
+
+        let response = self
+            .client
+            .chat()
+            .create(request)
+            .await
+            .map_err(|e| ProcessingError::ReplyDraftError(e.to_string()))?;
+        response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.as_deref())
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| ProcessingError::ReplyDraftError("Empty response".to_string()))
+    }
+}
+
+/// Values for the template variables; variables without a value are left out. With `redacted`
+/// the subject is the one that may be sent to external services.
+fn template_values(ticket: &ProcessedTicket, redacted: bool) -> HashMap<&'static str, String> {
+    let mut values = HashMap::new();
+    values.insert("ticket_id", ticket.ticket.id.clone());
+    values.insert("customer_id", ticket.ticket.customer_id.clone());
+    if let Some(tier) = &ticket.ticket.customer_tier {
+        values.insert("customer_tier", tier.clone());
+    }
+    let subject = if redacted {
+        ticket.external_subject()
+    } else {
+        ticket.ticket.subject.as_deref()
+    };
+    if let Some(subject) = subject {
+        values.insert("subject", subject.to_string());
+    }
+    if let ProcessingResult::Success(category) = &ticket.category {
+        values.insert("category", format!("{:?}", category));
+    }
+    if let ProcessingResult::Success(language) = &ticket.language {
+        values.insert("language", language_name(language));
+    }
+    if let ProcessingResult::Success(entities) = &ticket.entities {
+        if let Some(order_id) = entities.order_ids.first() {
+            values.insert("order_id", order_id.clone());
+            values.insert("order_ids", entities.order_ids.join(", "));
+        }
+        if let Some(product) = entities.products.first() {
+            values.insert("product", product.clone());
+        }
+        if let Some(version) = entities.versions.first() {
+            values.insert("version", version.clone());
+        }
+        if let Some(url) = entities.urls.first() {
+            values.insert("url", url.clone());
+        }
+        if let Some(status) = entities.http_status_codes.first() {
+            values.insert("http_status", status.to_string());
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockServer, chat_completion_response};
+    use crate::ticket::{SupportTicket, TicketEntities};
+    use chrono::Utc;
+
+    fn ticket(language: Language, category: TicketCategory, order_ids: &[&str]) -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            "t1".to_string(),
+            "I was charged twice, my email is ann@example.com".to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+        .with_language(ProcessingResult::Success(language))
+        .with_category(ProcessingResult::Success(category))
+        .with_entities(ProcessingResult::Success(TicketEntities {
+            order_ids: order_ids.iter().map(|id| id.to_string()).collect(),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_picks_best_template() {
+        let processor = ReplyDraftProcessor::from_dir(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("replies"),
+        )
+        .unwrap();
+
+        let with_order = processor
+            .draft(&ticket(
+                Language::English,
+                TicketCategory::Billing,
+                &["ORD-123456"],
+            ))
+            .unwrap();
+        assert_eq!(with_order.template, "billing-order");
+        assert_eq!(with_order.score, 1.0);
+        assert!(with_order.text.contains("about order ORD-123456."));
+        assert!(!with_order.polished);
+
+        let without_order = processor
+            .draft(&ticket(Language::English, TicketCategory::Billing, &[]))
+            .unwrap();
+        assert_eq!(without_order.template, "billing");
+        assert_eq!(without_order.score, 0.8);
+
+        let spanish = processor
+            .draft(&ticket(
+                Language::Spanish,
+                TicketCategory::Billing,
+                &["ORD-123456"],
+            ))
+            .unwrap();
+        assert_eq!(spanish.template, "billing-order-es");
+
+        assert!(
+            processor
+                .draft(&ticket(Language::English, TicketCategory::Sales, &[]))
+                .is_none()
+        );
+        assert!(ReplyTemplate::parse("bad", "version: 1\ncategory: Bills\n---\nHi").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_polishes_draft_with_llm() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                chat_completion_response("Hello, sorry about the double charge on ORD-123456."),
+            )
+        })
+        .await;
+        let template = ReplyTemplate::parse(
+            "billing-order",
+            "version: 1\ncategory: Billing\n---\nRe: {{subject}}\nAbout order {{order_id}}.",
+        )
+        .unwrap();
+        let processor = ReplyDraftProcessor::new(vec![template])
+            .with_llm_polish(
+                LlmConfig::default()
+                    .with_api_base(server.url.clone())
+                    .with_api_key("test-key"),
+            )
+            .unwrap();
+        let mut ticket = ticket(Language::English, TicketCategory::Billing, &["ORD-123456"])
+            .with_redaction(ProcessingResult::Success(crate::ticket::RedactedContent {
+                subject: Some("Charged twice, call [PHONE]".to_string()),
+                content: "I was charged twice, my email is [EMAIL]".to_string(),
+                pii_types: vec![crate::ticket::PiiType::Email],
+            }));
+
+        ticket.ticket = ticket
+            .ticket
+            .with_subject("Charged twice, call +1 415 555 0100");
+        let processed = processor.process(ticket).await;
+
+        assert_eq!(
+            processed.reply_draft,
+            ProcessingResult::Success(Some(ReplyDraft {
+                template: "billing-order".to_string(),
+                text: "Hello, sorry about the double charge on ORD-123456.".to_string(),
+                score: 0.7,
+                polished: true,
+            }))
+        );
+        assert_eq!(
+            processed.prompt_versions[PROMPT_VERSION_KEY],
+            "reply-polish-v1"
+        );
+        let body = server.requests()[0].json();
+        let user = body["messages"][1]["content"].as_str().unwrap();
+        assert!(user.contains("my email is [EMAIL]"));
+        // The subject in the draft is redacted like the one in the ticket block
+        assert!(user.contains(
+            "<draft>\nRe: Charged twice, call [PHONE]\nAbout order ORD-123456.\n</draft>"
+        ));
+        assert!(!user.contains("555"));
+        assert!(
+            body["messages"][0]["content"]
+                .as_str()
+                .unwrap()
+                .contains("Write in English.")
+        );
+    }
+}
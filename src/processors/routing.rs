@@ -0,0 +1,460 @@
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+use chrono::Duration;
+
+use crate::{
+    error::ProcessingError,
+    pipeline::{FieldMask, TicketProcessor},
+    ticket::{
+        ChurnRiskLevel, ProcessedTicket, ProcessingResult, Routing, TicketCategory, TicketPriority,
+    },
+    ticket_store::TicketStore,
+};
+use async_trait::async_trait;
+use language_enum::Language;
+use log::info;
+use serde::Deserialize;
+
+/// How a team assigns its tickets to its agents
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentStrategy {
+    /// Tickets stay in the team's queue
+    #[default]
+    None,
+    /// Agents take turns
+    RoundRobin,
+    /// The agent with the fewest recent tickets in the ticket store gets the ticket, see
+    /// `RoutingProcessor::with_load_window`
+    LeastLoaded,
+}
+
+/// A team and the tickets it handles. Empty lists match every ticket.
+///
+/// Tickets are classified into categories only, so there are no subcategory skills; products
+/// from entity extraction narrow a team down further. Unknown keys such as `subcategories` are
+/// rejected rather than ignored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TeamConfig {
+    pub name: String,
+    /// Queue the team's tickets go to, the team name by default
+    #[serde(default)]
+    pub queue: Option<String>,
+    #[serde(default)]
+    pub languages: Vec<Language>,
+    #[serde(default)]
+    pub categories: Vec<TicketCategory>,
+    /// Products from entity extraction, compared ignoring case
+    #[serde(default)]
+    pub products: Vec<String>,
+    /// Customer tiers, see `SupportTicket::customer_tier`
+    #[serde(default)]
+    pub tiers: Vec<String>,
+    #[serde(default)]
+    pub min_priority: Option<TicketPriority>,
+    /// Routes customers at risk of leaving, e.g. to a retention team. Needs churn risk
+    /// detection in the pipeline.
+    #[serde(default)]
+    pub min_churn_risk: Option<ChurnRiskLevel>,
+    #[serde(default)]
+    pub agents: Vec<String>,
+    #[serde(default)]
+    pub assignment: AssignmentStrategy,
+}
+
+impl TeamConfig {
+    /// Number of criteria the team sets; more specific teams win over general ones
+    fn specificity(&self) -> usize {
+        [
+            !self.languages.is_empty(),
+            !self.categories.is_empty(),
+            !self.products.is_empty(),
+            !self.tiers.is_empty(),
+            self.min_priority.is_some(),
+            self.min_churn_risk.is_some(),
+        ]
+        .into_iter()
+        .filter(|set| *set)
+        .count()
+    }
+
+    fn matches(&self, ticket: &ProcessedTicket) -> bool {
+        let language_matches = match &ticket.language {
+            ProcessingResult::Success(language) => self.languages.contains(language),
+            _ => false,
+        };
+        let category_matches = match &ticket.category {
+            ProcessingResult::Success(category) => self.categories.contains(category),
+            _ => false,
+        };
+        let product_matches = match &ticket.entities {
+            ProcessingResult::Success(entities) => entities
+                .products
+                .iter()
+                .any(|product| contains_ignoring_case(&self.products, product)),
+            _ => false,
+        };
+        let tier_matches = match &ticket.ticket.customer_tier {
+            Some(tier) => contains_ignoring_case(&self.tiers, tier),
+            None => false,
+        };
+        let priority_matches = match (&self.min_priority, &ticket.priority) {
+            (Some(minimum), ProcessingResult::Success(priority)) => priority >= minimum,
+            _ => false,
+        };
+        let churn_risk_matches = match (&self.min_churn_risk, &ticket.churn_risk) {
+            (Some(minimum), ProcessingResult::Success(risk)) => risk.level >= *minimum,
+            _ => false,
+        };
+        (self.languages.is_empty() || language_matches)
+            && (self.categories.is_empty() || category_matches)
+            && (self.products.is_empty() || product_matches)
+            && (self.tiers.is_empty() || tier_matches)
+            && (self.min_priority.is_none() || priority_matches)
+            && (self.min_churn_risk.is_none() || churn_risk_matches)
+    }
+}
+
+fn contains_ignoring_case(values: &[String], value: &str) -> bool {
+    values.iter().any(|v| v.eq_ignore_ascii_case(value))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoutingConfig {
+    pub teams: Vec<TeamConfig>,
+    /// Team for tickets that no team matches
+    #[serde(default)]
+    pub default_team: Option<String>,
+}
+
+impl RoutingConfig {
+    /// Loads the config from a JSON file, e.g.
+    /// `{"teams": [{"name": "billing-es", "languages": ["Spanish"], "categories": ["Billing"]}]}`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ProcessingError> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                ProcessingError::ConfigError(format!(
+                    "Failed to load routing config {}: {}",
+                    path.display(),
+                    e
+                ))
+            })
+    }
+
+    fn validate(&self) -> Result<(), ProcessingError> {
+        if let Some(default_team) = &self.default_team
+            && !self.teams.iter().any(|team| &team.name == default_team)
+        {
+            return Err(ProcessingError::ConfigError(format!(
+                "Default team {} is not configured",
+                default_team
+            )));
+        }
+        if let Some(team) = self
+            .teams
+            .iter()
+            .find(|team| team.assignment != AssignmentStrategy::None && team.agents.is_empty())
+        {
+            return Err(ProcessingError::ConfigError(format!(
+                "Team {} assigns tickets but has no agents",
+                team.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Decides which team handles the ticket, based on its language, category, products,
+/// priority, churn risk and the customer's tier, and optionally assigns it to one of the team's agents.
+/// The most specific matching team wins, and the first one configured among equally specific
+/// teams.
+pub struct RoutingProcessor {
+    config: RoutingConfig,
+    ticket_store: TicketStore,
+    /// Index of the next agent of each round-robin team
+    next_agent: Mutex<HashMap<String, usize>>,
+    load_window: Duration,
+}
+
+#[async_trait]
+impl TicketProcessor for RoutingProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "RoutingProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        let routing = match self.select_team(&ticket) {
+            Some(team) => ProcessingResult::Success(Routing {
+                team: team.name.clone(),
+                queue: team.queue.clone().unwrap_or_else(|| team.name.clone()),
+                agent: self.assign(team, &ticket).await,
+            }),
+            None => ProcessingResult::Error(ProcessingError::RoutingError(
+                "No team handles the ticket and no default team is configured".to_string(),
+            )),
+        };
+        let result = ticket.with_routing(routing);
+
+        info!("RoutingProcessor finished processing ticket: {}", ticket_id);
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::LANGUAGE | FieldMask::CATEGORY | FieldMask::PRIORITY
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        // Products and churn risk are used when their processors are configured
+        FieldMask::ENTITIES | FieldMask::CHURN_RISK
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::ROUTING
+    }
+}
+
+impl RoutingProcessor {
+    /// Balances least-loaded teams by the tickets assigned in `ticket_store`, which should be
+    /// the store of the pipeline, see `TicketPipeline::with_ticket_store`
+    pub fn new(config: RoutingConfig, ticket_store: TicketStore) -> Result<Self, ProcessingError> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            ticket_store,
+            next_agent: Mutex::new(HashMap::new()),
+            load_window: Duration::hours(8),
+        })
+    }
+
+    /// How far back from the ticket's timestamp the tickets of least-loaded agents are counted,
+    /// 8 hours by default
+    pub fn with_load_window(mut self, load_window: Duration) -> Self {
+        self.load_window = load_window;
+        self
+    }
+
+    pub fn select_team(&self, ticket: &ProcessedTicket) -> Option<&TeamConfig> {
+        let mut selected: Option<&TeamConfig> = None;
+        for team in self.config.teams.iter().filter(|team| team.matches(ticket)) {
+            if selected.is_none_or(|best| team.specificity() > best.specificity()) {
+                selected = Some(team);
+            }
+        }
+        selected.or_else(|| {
+            let default_team = self.config.default_team.as_ref()?;
+            self.config
+                .teams
+                .iter()
+                .find(|team| &team.name == default_team)
+        })
+    }
+
+    async fn assign(&self, team: &TeamConfig, ticket: &ProcessedTicket) -> Option<String> {
+        match team.assignment {
+            AssignmentStrategy::None => None,
+            AssignmentStrategy::RoundRobin => {
+                let mut next_agent = self.next_agent.lock().unwrap();
+                let next = next_agent.entry(team.name.clone()).or_default();
+                let agent = team.agents[*next % team.agents.len()].clone();
+                *next += 1;
+                Some(agent)
+            }
+            AssignmentStrategy::LeastLoaded => {
+                let mut load: HashMap<&str, usize> = team
+                    .agents
+                    .iter()
+                    .map(|agent| (agent.as_str(), 0))
+                    .collect();
+                // Anchored on the ticket rather than the clock, so replayed tickets are
+                // balanced as they would have been when they arrived
+                let until = ticket.ticket.timestamp;
+                self.ticket_store
+                    .visit_since(until - self.load_window, |earlier| {
+                        if let ProcessingResult::Success(Routing {
+                            agent: Some(agent), ..
+                        }) = &earlier.routing
+                            && earlier.ticket.id != ticket.ticket.id
+                            && earlier.ticket.timestamp <= until
+                            && let Some(count) = load.get_mut(agent.as_str())
+                        {
+                            *count += 1;
+                        }
+                    })
+                    .await;
+                // The first agent configured wins ties
+                team.agents
+                    .iter()
+                    .min_by_key(|agent| load[agent.as_str()])
+                    .cloned()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ticket::{ChurnRisk, SupportTicket, TicketEntities};
+    use chrono::Utc;
+
+    const CONFIG: &str = r#"{
+        "teams": [
+            {"name": "general", "agents": ["gina", "gus"], "assignment": "round_robin"},
+            {"name": "billing", "categories": ["Billing"]},
+            {"name": "billing-es", "queue": "facturacion", "languages": ["Spanish"], "categories": ["Billing"]},
+            {"name": "sync", "products": ["Acme Sync"]},
+            {"name": "enterprise", "tiers": ["enterprise"], "min_priority": "High",
+             "agents": ["erin", "eli"], "assignment": "least_loaded"}
+        ],
+        "default_team": "general"
+    }"#;
+
+    fn ticket(id: &str, language: Language, category: TicketCategory) -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            id.to_string(),
+            "Test ticket content".to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+        .with_language(ProcessingResult::Success(language))
+        .with_category(ProcessingResult::Success(category))
+        .with_priority(ProcessingResult::Success(TicketPriority::Medium))
+    }
+
+    fn team(processor: &RoutingProcessor, ticket: &ProcessedTicket) -> String {
+        processor.select_team(ticket).unwrap().name.clone()
+    }
+
+    #[test]
+    fn test_selects_most_specific_team() {
+        let config: RoutingConfig = serde_json::from_str(CONFIG).unwrap();
+        let processor = RoutingProcessor::new(config.clone(), TicketStore::new()).unwrap();
+
+        let spanish_billing = ticket("t1", Language::Spanish, TicketCategory::Billing);
+        assert_eq!(team(&processor, &spanish_billing), "billing-es");
+        let english_billing = ticket("t2", Language::English, TicketCategory::Billing);
+        assert_eq!(team(&processor, &english_billing), "billing");
+        let sync = ticket("t3", Language::English, TicketCategory::Technical).with_entities(
+            ProcessingResult::Success(TicketEntities {
+                products: vec!["Acme Sync".to_string()],
+                ..Default::default()
+            }),
+        );
+        assert_eq!(team(&processor, &sync), "sync");
+
+        // Enterprise customers only go to their team when the ticket is urgent enough
+        let mut enterprise = ticket("t4", Language::English, TicketCategory::Technical);
+        enterprise.ticket = enterprise.ticket.with_customer_tier("Enterprise");
+        assert_eq!(team(&processor, &enterprise), "general");
+        let enterprise = enterprise.with_priority(ProcessingResult::Success(TicketPriority::High));
+        assert_eq!(team(&processor, &enterprise), "enterprise");
+
+        // Customers at risk of leaving go to retention, whatever the ticket is about
+        let mut with_retention = config.clone();
+        with_retention.teams.push(
+            serde_json::from_str(r#"{"name": "retention", "min_churn_risk": "High"}"#).unwrap(),
+        );
+        let processor = RoutingProcessor::new(with_retention, TicketStore::new()).unwrap();
+        assert!(processor.optional_fields().contains(FieldMask::CHURN_RISK));
+        let churn_risk = |level| {
+            ticket("t5", Language::English, TicketCategory::General).with_churn_risk(
+                ProcessingResult::Success(ChurnRisk {
+                    level,
+                    evidence: vec!["asks to cancel the subscription".to_string()],
+                }),
+            )
+        };
+        assert_eq!(
+            team(&processor, &churn_risk(ChurnRiskLevel::High)),
+            "retention"
+        );
+        assert_eq!(
+            team(&processor, &churn_risk(ChurnRiskLevel::Low)),
+            "general"
+        );
+
+        let mut invalid = config;
+        invalid.default_team = Some("nobody".to_string());
+        assert!(RoutingProcessor::new(invalid, TicketStore::new()).is_err());
+        assert!(
+            serde_json::from_str::<RoutingConfig>(
+                r#"{"teams": [{"name": "refunds", "subcategories": ["Refund"]}]}"#
+            )
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_assigns_agents() {
+        let config: RoutingConfig = serde_json::from_str(CONFIG).unwrap();
+        let store = TicketStore::new();
+        let processor = RoutingProcessor::new(config, store.clone()).unwrap();
+
+        let mut agents = Vec::new();
+        for id in ["t1", "t2", "t3"] {
+            let processed = processor
+                .process(ticket(id, Language::English, TicketCategory::General))
+                .await;
+            let ProcessingResult::Success(routing) = processed.routing else {
+                panic!("Expected a routing, got {:?}", processed.routing);
+            };
+            assert_eq!(routing.queue, "general");
+            agents.push(routing.agent.unwrap());
+        }
+        assert_eq!(agents, vec!["gina", "gus", "gina"]);
+
+        // Erin's tickets from yesterday no longer count
+        for id in ["t0", "t00"] {
+            let mut old = ticket(id, Language::English, TicketCategory::Technical).with_routing(
+                ProcessingResult::Success(Routing {
+                    team: "enterprise".to_string(),
+                    queue: "enterprise".to_string(),
+                    agent: Some("eli".to_string()),
+                }),
+            );
+            old.ticket.timestamp = Utc::now() - Duration::days(1);
+            store.add_ticket(old).await;
+        }
+        // Erin already has a ticket
+        store
+            .add_ticket(
+                ticket("t4", Language::English, TicketCategory::Technical).with_routing(
+                    ProcessingResult::Success(Routing {
+                        team: "enterprise".to_string(),
+                        queue: "enterprise".to_string(),
+                        agent: Some("erin".to_string()),
+                    }),
+                ),
+            )
+            .await;
+        let mut urgent = ticket("t5", Language::English, TicketCategory::Technical)
+            .with_priority(ProcessingResult::Success(TicketPriority::Critical));
+        urgent.ticket = urgent.ticket.with_customer_tier("enterprise");
+        let processed = processor.process(urgent).await;
+        assert_eq!(
+            processed.routing,
+            ProcessingResult::Success(Routing {
+                team: "enterprise".to_string(),
+                queue: "enterprise".to_string(),
+                agent: Some("eli".to_string()),
+            })
+        );
+
+        // A replayed ticket from yesterday is balanced against the tickets of its own time
+        let mut replayed = ticket("t6", Language::English, TicketCategory::Technical)
+            .with_priority(ProcessingResult::Success(TicketPriority::Critical));
+        replayed.ticket = replayed.ticket.with_customer_tier("enterprise");
+        replayed.ticket.timestamp = Utc::now() - Duration::days(1) + Duration::minutes(1);
+        let processed = processor.process(replayed).await;
+        let ProcessingResult::Success(routing) = processed.routing else {
+            panic!("Expected a routing, got {:?}", processed.routing);
+        };
+        assert_eq!(routing.agent.as_deref(), Some("erin"));
+    }
+}
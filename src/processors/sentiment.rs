@@ -1,18 +1,201 @@
-use std::env;
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use crate::{
     error::ProcessingError,
     pipeline::{FieldMask, TicketProcessor},
-    ticket::{ProcessedTicket, ProcessingResult, SentimentScore},
+    ticket::{ProcessedTicket, ProcessingResult, SentimentLabel, SentimentScore, normalize_label},
+    truncation::TruncationConfig,
 };
 use async_trait::async_trait;
 use log::info;
 use serde::Deserialize;
 use serde_json::json;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{Instant, timeout_at},
+};
+
+const HUGGING_FACE_API_BASE: &str = "https://router.huggingface.co/hf-inference/models";
+
+/// Default limit on the text sent to the model, below the 512 tokens most sentiment models accept
+const DEFAULT_MAX_TOKENS: usize = 500;
+
+/// Settings for the text-classification endpoint used for sentiment analysis
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentimentConfig {
+    /// Base URL of the inference API. The model ID is appended to it as a path segment.
+    pub api_base: String,
+    /// Model ID, e.g. `tabularisai/multilingual-sentiment-analysis`. Leave empty for servers
+    /// that serve a single model directly at `api_base`.
+    pub model: String,
+    pub api_token: Option<String>,
+    /// Maps the labels returned by the model onto sentiment labels
+    pub label_map: SentimentLabelMap,
+    /// Number of labels to request scores for. Should be at least the number of labels the
+    /// model has, so that the full distribution is returned.
+    pub top_k: u32,
+    /// Batches requests from concurrent tickets into one call when set
+    pub batching: Option<SentimentBatchConfig>,
+    /// Limits on the ticket text sent to the model
+    pub truncation: TruncationConfig,
+}
+
+impl Default for SentimentConfig {
+    fn default() -> Self {
+        Self {
+            api_base: HUGGING_FACE_API_BASE.to_string(),
+            model: "tabularisai/multilingual-sentiment-analysis".to_string(),
+            api_token: None,
+            label_map: SentimentLabelMap::five_class(),
+            top_k: 5,
+            batching: None,
+            truncation: TruncationConfig::default().with_max_tokens(DEFAULT_MAX_TOKENS),
+        }
+    }
+}
+
+/// A table mapping model output labels onto sentiment labels.
+///
+/// Lookups ignore case and `_`/`-` separators. Labels missing from the table are parsed with
+/// `SentimentLabel::try_from`, so only model specific labels such as `LABEL_0` need an entry.
+/// Unknown labels are reported as errors rather than treated as neutral.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(from = "HashMap<String, SentimentLabel>")]
+pub struct SentimentLabelMap {
+    labels: HashMap<String, SentimentLabel>,
+}
+
+impl SentimentLabelMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Five class models such as `tabularisai/multilingual-sentiment-analysis`,
+    /// where `LABEL_0` is very negative and `LABEL_4` is very positive
+    pub fn five_class() -> Self {
+        Self::new()
+            .with_label("LABEL_0", SentimentLabel::VeryNegative)
+            .with_label("LABEL_1", SentimentLabel::Negative)
+            .with_label("LABEL_2", SentimentLabel::Neutral)
+            .with_label("LABEL_3", SentimentLabel::Positive)
+            .with_label("LABEL_4", SentimentLabel::VeryPositive)
+    }
+
+    /// Three class models such as `cardiffnlp/twitter-roberta-base-sentiment`,
+    /// where `LABEL_0` is negative, `LABEL_1` neutral and `LABEL_2` positive
+    pub fn three_class() -> Self {
+        Self::new()
+            .with_label("LABEL_0", SentimentLabel::Negative)
+            .with_label("LABEL_1", SentimentLabel::Neutral)
+            .with_label("LABEL_2", SentimentLabel::Positive)
+    }
+
+    pub fn with_label(mut self, label: &str, sentiment: SentimentLabel) -> Self {
+        self.labels.insert(normalize_label(label), sentiment);
+        self
+    }
+
+    pub fn resolve(&self, label: &str) -> Result<SentimentLabel, ProcessingError> {
+        match self.labels.get(&normalize_label(label)) {
+            Some(sentiment) => Ok(sentiment.clone()),
+            None => SentimentLabel::try_from(label),
+        }
+    }
+}
+
+impl From<HashMap<String, SentimentLabel>> for SentimentLabelMap {
+    fn from(labels: HashMap<String, SentimentLabel>) -> Self {
+        labels
+            .into_iter()
+            .fold(Self::new(), |map, (label, sentiment)| {
+                map.with_label(&label, sentiment)
+            })
+    }
+}
+
+impl SentimentConfig {
+    /// Builds a config from the `HUGGING_FACE_API_TOKEN`, `SENTIMENT_API_BASE` and
+    /// `SENTIMENT_MODEL` environment variables, using the defaults for anything that is not set.
+    /// Batching is enabled by `SENTIMENT_BATCH_SIZE`, with `SENTIMENT_BATCH_WAIT_MS` as the
+    /// longest wait.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let batching = env::var("SENTIMENT_BATCH_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .map(|max_batch_size| {
+                let batch_defaults = SentimentBatchConfig::default();
+                SentimentBatchConfig {
+                    max_batch_size,
+                    max_wait: env::var("SENTIMENT_BATCH_WAIT_MS")
+                        .ok()
+                        .and_then(|wait| wait.parse().ok())
+                        .map_or(batch_defaults.max_wait, Duration::from_millis),
+                }
+            });
+        Self {
+            api_base: env::var("SENTIMENT_API_BASE").unwrap_or(defaults.api_base),
+            model: env::var("SENTIMENT_MODEL").unwrap_or(defaults.model),
+            api_token: env::var("HUGGING_FACE_API_TOKEN").ok(),
+            batching,
+            ..defaults
+        }
+    }
+
+    pub fn with_api_base(mut self, api_base: impl Into<String>) -> Self {
+        self.api_base = api_base.into();
+        self
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    pub fn with_api_token(mut self, api_token: impl Into<String>) -> Self {
+        self.api_token = Some(api_token.into());
+        self
+    }
+
+    /// Replaces the label mapping, for models whose labels differ from the default model
+    pub fn with_label_map(mut self, label_map: SentimentLabelMap) -> Self {
+        self.label_map = label_map;
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn with_batching(mut self, batching: SentimentBatchConfig) -> Self {
+        self.batching = Some(batching);
+        self
+    }
+
+    pub fn with_truncation(mut self, truncation: TruncationConfig) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    fn url(&self) -> String {
+        let api_base = self.api_base.trim_end_matches('/');
+        if self.model.is_empty() {
+            api_base.to_string()
+        } else {
+            format!("{}/{}", api_base, self.model)
+        }
+    }
+}
 
 pub struct SentimentProcessor {
-    client: reqwest::Client,
-    api_token: String,
+    client: SentimentClient,
+    batcher: Option<SentimentBatcher>,
 }
 
 #[async_trait]
@@ -24,11 +207,26 @@ impl TicketProcessor for SentimentProcessor {
         );
 
         let ticket_id = ticket.ticket.id.clone();
-        let sentiment = match self.analyze_sentiment(&ticket.ticket.content).await {
+        if let Some(reason) = ticket.skip_reason() {
+            info!(
+                "SentimentProcessor skipped ticket {}: {}",
+                ticket_id, reason
+            );
+            return ticket.with_sentiment(ProcessingResult::Skipped(reason));
+        }
+        let text = self
+            .client
+            .config
+            .truncation
+            .apply(ticket.external_content());
+        let sentiment = match self.analyze_sentiment(&text.text).await {
             Ok(sentiment) => ProcessingResult::Success(sentiment),
             Err(err) => ProcessingResult::Error(err),
         };
-        let result = ticket.with_sentiment(sentiment);
+        let mut result = ticket.with_sentiment(sentiment);
+        if let Some(signal) = text.review_signal("sentiment") {
+            result = result.with_review_signal(signal);
+        }
 
         info!(
             "SentimentProcessor finished processing ticket: {}",
@@ -41,6 +239,10 @@ impl TicketProcessor for SentimentProcessor {
         FieldMask::empty()
     }
 
+    fn optional_fields(&self) -> FieldMask {
+        FieldMask::REDACTION | FieldMask::MESSAGE_KIND
+    }
+
     fn output_fields(&self) -> FieldMask {
         FieldMask::SENTIMENT
     }
@@ -48,26 +250,77 @@ impl TicketProcessor for SentimentProcessor {
 
 impl SentimentProcessor {
     pub fn new() -> Result<Self, ProcessingError> {
-        let api_token = env::var("HUGGING_FACE_API_TOKEN").map_err(|_| {
-            ProcessingError::SentimentAnalysis("HUGGING_FACE_API_TOKEN not set".to_string())
-        })?;
+        Self::with_config(SentimentConfig::from_env())
+    }
 
+    pub fn with_config(config: SentimentConfig) -> Result<Self, ProcessingError> {
+        if config.api_base == HUGGING_FACE_API_BASE && config.api_token.is_none() {
+            return Err(ProcessingError::SentimentAnalysis(
+                "HUGGING_FACE_API_TOKEN not set".to_string(),
+            ));
+        }
+
+        let batcher = config.batching.clone().map(SentimentBatcher::new);
         Ok(SentimentProcessor {
-            client: reqwest::Client::new(),
-            api_token,
+            client: SentimentClient {
+                http: reqwest::Client::new(),
+                config: Arc::new(config),
+            },
+            batcher,
         })
     }
 
     async fn analyze_sentiment(&self, text: &str) -> Result<SentimentScore, ProcessingError> {
-        let url = "https://router.huggingface.co/hf-inference/models/tabularisai/multilingual-sentiment-analysis";
-        let response = self
-            .client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
+        match &self.batcher {
+            Some(batcher) => batcher.analyze(&self.client, text).await,
+            None => {
+                let mut scores = self.client.analyze(&[text.to_string()]).await?;
+                scores.remove(0)
+            }
+        }
+    }
+}
+
+/// Settings for collecting sentiment requests from concurrent tickets into one API call
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentimentBatchConfig {
+    /// A batch is sent as soon as it holds this many texts
+    pub max_batch_size: usize,
+    /// A batch is sent at the latest this long after its first text arrived
+    pub max_wait: Duration,
+}
+
+impl Default for SentimentBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 16,
+            max_wait: Duration::from_millis(50),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SentimentClient {
+    http: reqwest::Client,
+    config: Arc<SentimentConfig>,
+}
+
+impl SentimentClient {
+    /// Requests the sentiment of several texts in one call, returning one result per text. A
+    /// failed request fails every text, an unknown label only the text it was returned for.
+    async fn analyze(
+        &self,
+        texts: &[String],
+    ) -> Result<Vec<Result<SentimentScore, ProcessingError>>, ProcessingError> {
+        let mut request = self.http.post(self.config.url());
+        if let Some(api_token) = &self.config.api_token {
+            request = request.header("Authorization", format!("Bearer {}", api_token));
+        }
+        let response = request
             .header("Content-Type", "application/json")
             .json(&json!({
-                "inputs": text,
-                "parameters": { "top_k": 1 }
+                "inputs": texts,
+                "parameters": { "top_k": self.config.top_k }
             }))
             .send()
             .await
@@ -77,22 +330,116 @@ impl SentimentProcessor {
             .error_for_status_ref()
             .map_err(|e| ProcessingError::SentimentAnalysis(format!("HTTP error: {}", e)))?;
 
-        // Parse the response to extract sentiment score. Response will be in the format: [[{"label":"Very Positive","score":0.6382827162742615}]]
-        // Label will be one of "Very Positive", "Positive", "Neutral", "Negative", "Very Negative" and score is a float between 0.0 and 1.0.
+        // Parse the response to extract sentiment scores. Response will be in the format:
+        // [[{"label":"Very Positive","score":0.63},{"label":"Positive","score":0.21},...], ...]
+        // with one list per input text and one entry per label. Labels depend on the model and
+        // are mapped through the configured label map, scores are floats between 0.0 and 1.0.
         let parsed: Vec<Vec<HuggingFaceResponse>> = response
             .json()
             .await
             .map_err(|e| ProcessingError::SentimentAnalysis(e.to_string()))?;
 
-        let hugging_face_sentiment = parsed.first().and_then(|v| v.first()).ok_or_else(|| {
-            ProcessingError::SentimentAnalysis("Invalid response format".to_string())
-        })?;
+        if parsed.len() != texts.len() {
+            return Err(ProcessingError::SentimentAnalysis(format!(
+                "Expected {} results but got {}",
+                texts.len(),
+                parsed.len()
+            )));
+        }
 
-        let sentiment = SentimentScore {
-            label: hugging_face_sentiment.label.as_str().into(),
-            confidence: hugging_face_sentiment.score,
-        };
-        Ok(sentiment)
+        Ok(parsed
+            .into_iter()
+            .map(|hugging_face_scores| {
+                let distribution = hugging_face_scores
+                    .iter()
+                    .map(|entry| Ok((self.config.label_map.resolve(&entry.label)?, entry.score)))
+                    .collect::<Result<Vec<_>, ProcessingError>>()?;
+
+                SentimentScore::from_distribution(distribution).ok_or_else(|| {
+                    ProcessingError::SentimentAnalysis("Invalid response format".to_string())
+                })
+            })
+            .collect())
+    }
+}
+
+type BatchItem = (
+    String,
+    oneshot::Sender<Result<SentimentScore, ProcessingError>>,
+);
+
+/// Collects texts from concurrent calls into micro-batches that are flushed when they are full
+/// or when the oldest text has waited `max_wait`. The background task is started on first use,
+/// on the runtime of the first caller, and stops when the processor is dropped.
+struct SentimentBatcher {
+    config: SentimentBatchConfig,
+    sender: OnceLock<mpsc::UnboundedSender<BatchItem>>,
+}
+
+impl SentimentBatcher {
+    fn new(config: SentimentBatchConfig) -> Self {
+        Self {
+            config,
+            sender: OnceLock::new(),
+        }
+    }
+
+    async fn analyze(
+        &self,
+        client: &SentimentClient,
+        text: &str,
+    ) -> Result<SentimentScore, ProcessingError> {
+        let sender = self.sender.get_or_init(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(run_batches(receiver, client.clone(), self.config.clone()));
+            sender
+        });
+
+        let (result_sender, result_receiver) = oneshot::channel();
+        sender
+            .send((text.to_string(), result_sender))
+            .map_err(|_| {
+                ProcessingError::SentimentAnalysis("Sentiment batcher stopped".to_string())
+            })?;
+        result_receiver.await.map_err(|_| {
+            ProcessingError::SentimentAnalysis("Sentiment batch was dropped".to_string())
+        })?
+    }
+}
+
+async fn run_batches(
+    mut receiver: mpsc::UnboundedReceiver<BatchItem>,
+    client: SentimentClient,
+    config: SentimentBatchConfig,
+) {
+    while let Some(first) = receiver.recv().await {
+        let deadline = Instant::now() + config.max_wait;
+        let mut batch = vec![first];
+        while batch.len() < config.max_batch_size {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(item)) => batch.push(item),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        // Send the batch in the background so the next batch can be collected meanwhile
+        let client = client.clone();
+        tokio::spawn(async move {
+            let (texts, senders): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+            info!("Sending sentiment batch of {} texts", texts.len());
+            match client.analyze(&texts).await {
+                Ok(scores) => {
+                    for (sender, score) in senders.into_iter().zip(scores) {
+                        let _ = sender.send(score);
+                    }
+                }
+                Err(err) => {
+                    for sender in senders {
+                        let _ = sender.send(Err(err.clone()));
+                    }
+                }
+            }
+        });
     }
 }
 
@@ -101,3 +448,301 @@ struct HuggingFaceResponse {
     label: String,
     score: f32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::MockServer;
+    use crate::ticket::ReviewSignal;
+
+    #[tokio::test]
+    async fn test_analyze_sentiment_with_custom_endpoint_and_labels() {
+        let server =
+            MockServer::start(|_| (200, r#"[[{"label":"NEGATIVE","score":0.97}]]"#.to_string()))
+                .await;
+        let label_map = SentimentLabelMap::new()
+            .with_label("POSITIVE", SentimentLabel::Positive)
+            .with_label("NEGATIVE", SentimentLabel::Negative);
+        let config = SentimentConfig::default()
+            .with_api_base(format!("{}/models/", server.url))
+            .with_model("distilbert-sst2")
+            .with_label_map(label_map);
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let sentiment = processor.analyze_sentiment("This is broken again").await;
+
+        assert_eq!(
+            sentiment,
+            Ok(SentimentScore::new(SentimentLabel::Negative, 0.97))
+        );
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/models/distilbert-sst2");
+        assert_eq!(
+            requests[0].json()["inputs"],
+            json!(["This is broken again"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_long_ticket_is_truncated() {
+        let server =
+            MockServer::start(|_| (200, r#"[[{"label":"LABEL_1","score":0.8}]]"#.to_string()))
+                .await;
+        let config = SentimentConfig::default()
+            .with_api_base(server.url.clone())
+            .with_truncation(TruncationConfig::default().with_max_chars(100));
+        let processor = SentimentProcessor::with_config(config).unwrap();
+        let content = format!(
+            "Upload fails with this log:\n{}\nPlease help!\n\nOn Mon, Jan 6, 2025 Support wrote:\n> Hi",
+            "ERROR timeout\n".repeat(50)
+        );
+
+        let processed = processor
+            .process(ProcessedTicket::new(crate::ticket::SupportTicket::new(
+                "t1".to_string(),
+                content.clone(),
+                chrono::Utc::now(),
+                "customer1".to_string(),
+            )))
+            .await;
+
+        let sent = server.requests()[0].json()["inputs"][0]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(sent.chars().count(), 100);
+        assert!(sent.starts_with("Upload fails with this log:"));
+        assert!(sent.ends_with("Please help!"));
+        assert_eq!(
+            processed.review_signals,
+            vec![ReviewSignal::ContentTruncated {
+                processor: "sentiment".to_string(),
+                original_chars: content.chars().count(),
+                sent_chars: 100,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skips_messages_that_are_not_genuine() {
+        let server =
+            MockServer::start(|_| (200, r#"[[{"label":"LABEL_1","score":0.8}]]"#.to_string()))
+                .await;
+        let config = SentimentConfig::default().with_api_base(server.url.clone());
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let processed = processor
+            .process(
+                ProcessedTicket::new(crate::ticket::SupportTicket::new(
+                    "t1".to_string(),
+                    "Undeliverable: Re: your ticket".to_string(),
+                    chrono::Utc::now(),
+                    "customer1".to_string(),
+                ))
+                .with_message_kind(ProcessingResult::Success(
+                    crate::ticket::MessageKind::Bounce,
+                )),
+            )
+            .await;
+
+        assert_eq!(
+            processed.sentiment,
+            ProcessingResult::Skipped("bounced message".to_string())
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_analyze_sentiment_full_distribution() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                r#"[[{"label":"Negative","score":0.5},{"label":"Very Negative","score":0.25},
+                    {"label":"Neutral","score":0.25},{"label":"Positive","score":0.0},
+                    {"label":"Very Positive","score":0.0}]]"#
+                    .to_string(),
+            )
+        })
+        .await;
+        let config = SentimentConfig::default().with_api_base(server.url.clone());
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let sentiment = processor
+            .analyze_sentiment("Still not fixed")
+            .await
+            .unwrap();
+
+        assert_eq!(sentiment.label, SentimentLabel::Negative);
+        assert_eq!(sentiment.confidence, 0.5);
+        assert_eq!(sentiment.distribution.len(), 5);
+        assert_eq!(sentiment.polarity, -0.5);
+        assert_eq!(server.requests()[0].json()["parameters"]["top_k"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_batches_concurrent_requests() {
+        // Answers each input with a label derived from its text
+        let server = MockServer::start(|request| {
+            let results: Vec<_> = request.json()["inputs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|input| json!([{"label": input.as_str().unwrap(), "score": 0.9}]))
+                .collect();
+            (200, json!(results).to_string())
+        })
+        .await;
+        let config = SentimentConfig::default()
+            .with_api_base(server.url.clone())
+            .with_batching(SentimentBatchConfig {
+                max_batch_size: 3,
+                max_wait: Duration::from_secs(10),
+            });
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let (negative, positive, neutral) = tokio::join!(
+            processor.analyze_sentiment("Negative"),
+            processor.analyze_sentiment("Positive"),
+            processor.analyze_sentiment("Neutral"),
+        );
+
+        assert_eq!(negative.unwrap().label, SentimentLabel::Negative);
+        assert_eq!(positive.unwrap().label, SentimentLabel::Positive);
+        assert_eq!(neutral.unwrap().label, SentimentLabel::Neutral);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].json()["inputs"].as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_batch_flushed_after_max_wait() {
+        let server =
+            MockServer::start(|_| (200, r#"[[{"label":"Positive","score":0.9}]]"#.to_string()))
+                .await;
+        let config = SentimentConfig::default()
+            .with_api_base(server.url.clone())
+            .with_batching(SentimentBatchConfig {
+                max_batch_size: 10,
+                max_wait: Duration::from_millis(20),
+            });
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let sentiment = processor.analyze_sentiment("Thanks!").await;
+
+        assert_eq!(sentiment.unwrap().label, SentimentLabel::Positive);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_label_fails_only_its_text() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                r#"[[{"label":"Positive","score":0.9}],[{"label":"LABEL_9","score":0.8}]]"#
+                    .to_string(),
+            )
+        })
+        .await;
+        let config = SentimentConfig::default()
+            .with_api_base(server.url.clone())
+            .with_batching(SentimentBatchConfig {
+                max_batch_size: 2,
+                max_wait: Duration::from_secs(10),
+            });
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let (known, unknown) = tokio::join!(
+            processor.analyze_sentiment("Thanks!"),
+            processor.analyze_sentiment("Hmm"),
+        );
+
+        assert_eq!(known.unwrap().label, SentimentLabel::Positive);
+        assert!(matches!(
+            unknown,
+            Err(ProcessingError::SentimentAnalysis(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_batch_error_is_returned_to_every_caller() {
+        let server = MockServer::start(|_| (200, "[]".to_string())).await;
+        let config = SentimentConfig::default()
+            .with_api_base(server.url.clone())
+            .with_batching(SentimentBatchConfig {
+                max_batch_size: 2,
+                max_wait: Duration::from_secs(10),
+            });
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let (first, second) = tokio::join!(
+            processor.analyze_sentiment("one"),
+            processor.analyze_sentiment("two"),
+        );
+
+        assert!(matches!(first, Err(ProcessingError::SentimentAnalysis(_))));
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_sentiment_unknown_label() {
+        let server =
+            MockServer::start(|_| (200, r#"[[{"label":"LABEL_9","score":0.8}]]"#.to_string()))
+                .await;
+        let config = SentimentConfig::default().with_api_base(server.url.clone());
+        let processor = SentimentProcessor::with_config(config).unwrap();
+
+        let sentiment = processor.analyze_sentiment("Hello").await;
+
+        assert!(matches!(
+            sentiment,
+            Err(ProcessingError::SentimentAnalysis(_))
+        ));
+    }
+
+    #[test]
+    fn test_label_map_resolve() {
+        let five_class = SentimentLabelMap::five_class();
+        assert_eq!(
+            five_class.resolve("LABEL_0"),
+            Ok(SentimentLabel::VeryNegative)
+        );
+        assert_eq!(
+            five_class.resolve("label-4"),
+            Ok(SentimentLabel::VeryPositive)
+        );
+        assert_eq!(
+            five_class.resolve("Very Negative"),
+            Ok(SentimentLabel::VeryNegative)
+        );
+
+        let three_class = SentimentLabelMap::three_class();
+        assert_eq!(three_class.resolve("LABEL_0"), Ok(SentimentLabel::Negative));
+        assert!(three_class.resolve("LABEL_3").is_err());
+
+        let configured: SentimentLabelMap =
+            serde_json::from_str(r#"{"Very Bad": "VeryNegative", "GOOD": "Positive"}"#).unwrap();
+        assert_eq!(
+            configured.resolve("very_bad"),
+            Ok(SentimentLabel::VeryNegative)
+        );
+        assert_eq!(configured.resolve("good"), Ok(SentimentLabel::Positive));
+        assert_eq!(configured.resolve("2 stars"), Ok(SentimentLabel::Negative));
+    }
+
+    #[test]
+    fn test_hugging_face_requires_token() {
+        assert!(SentimentProcessor::with_config(SentimentConfig::default()).is_err());
+        assert!(
+            SentimentProcessor::with_config(SentimentConfig::default().with_api_token("token"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_url_without_model() {
+        let config = SentimentConfig::default()
+            .with_api_base("http://localhost:8080/predict")
+            .with_model("");
+        assert_eq!(config.url(), "http://localhost:8080/predict");
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+};
+
+use async_openai::{Client, config::OpenAIConfig, types::*};
+use regex::Regex;
+use schemars::{JsonSchema, schema_for};
+use serde::Deserialize;
+
+use crate::{
+    error::ProcessingError,
+    llm::LlmConfig,
+    pipeline::{FieldMask, TicketProcessor},
+    processors::classification::ClassificationProcessor,
+    prompt::{PromptTemplate, delimit_ticket},
+    ticket::{ProcessedTicket, ProcessingResult, TicketSummary},
+    truncation::TruncationConfig,
+};
+use async_trait::async_trait;
+use log::{info, warn};
+
+/// The built-in prompt
+const DEFAULT_PROMPT_TEMPLATE: &str = include_str!("../../prompts/summary.prompt");
+
+/// Key under which the prompt version is recorded in `ProcessedTicket::prompt_versions`
+const PROMPT_VERSION_KEY: &str = "summary";
+
+/// Longest title taken from a sentence of the ticket
+const MAX_TITLE_CHARS: usize = 80;
+
+static SENTENCE_END: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[.!?]+(\s+|$)").unwrap());
+
+static WORD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[\p{L}\p{N}]{2,}").unwrap());
+
+/// Words that say little about the problem, including greetings, in English, Spanish, German
+/// and French
+static STOPWORDS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
+    [
+        "the", "and", "for", "are", "but", "not", "you", "your", "with", "this", "that", "have",
+        "has", "had", "was", "were", "is", "it", "its", "of", "to", "in", "on", "at", "as", "be",
+        "an", "or", "if", "so", "we", "me", "my", "our", "us", "they", "them", "can", "could",
+        "would", "will", "do", "does", "did", "am", "there", "what", "when", "how", "all", "any",
+        "from", "by", "just", "also", "very", "please", "hi", "hello", "hey", "dear", "thanks",
+        "thank", "regards", "team", "el", "la", "los", "las", "de", "que", "en", "un", "una", "es",
+        "por", "con", "para", "mi", "hola", "gracias", "der", "die", "das", "und", "ist", "ich",
+        "nicht", "mit", "ein", "eine", "zu", "hallo", "danke", "le", "les", "des", "et", "est",
+        "je", "pas", "une", "pour", "bonjour", "merci",
+    ]
+    .into_iter()
+    .collect()
+});
+
+/// Writes a one-line title and a few bullet points for the ticket. Uses the configured LLM
+/// when there is one, and otherwise, or when the LLM fails, picks the most representative
+/// sentences of the ticket.
+pub struct SummarizationProcessor {
+    max_bullets: usize,
+    llm: Option<LlmSummarizer>,
+}
+
+struct LlmSummarizer {
+    client: Client<OpenAIConfig>,
+    config: LlmConfig,
+    prompt_template: PromptTemplate,
+    truncation: TruncationConfig,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct OpenAISummaryResponse {
+    title: String,
+    bullets: Vec<String>,
+}
+
+#[async_trait]
+impl TicketProcessor for SummarizationProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "SummarizationProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        if let Some(reason) = ticket.skip_reason() {
+            info!(
+                "SummarizationProcessor skipped ticket {}: {}",
+                ticket_id, reason
+            );
+            return ticket.with_summary(ProcessingResult::Skipped(reason));
+        }
+        let mut prompt_version = None;
+        let summary = match &self.llm {
+            Some(llm) => match llm.summarize(&ticket, self.max_bullets).await {
+                Ok(summary) => {
+                    prompt_version = Some(&llm.prompt_template.version);
+                    summary
+                }
+                Err(e) => {
+                    warn!(
+                        "Falling back to an extractive summary for ticket {}: {}",
+                        ticket_id, e
+                    );
+                    self.extractive_summary(&ticket)
+                }
+            },
+            None => self.extractive_summary(&ticket),
+        };
+        let mut result = ticket.with_summary(ProcessingResult::Success(summary));
+        if let Some(version) = prompt_version {
+            result = result.with_prompt_version(PROMPT_VERSION_KEY, version);
+        }
+
+        info!(
+            "SummarizationProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::empty()
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        FieldMask::REDACTION | FieldMask::MESSAGE_KIND
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::SUMMARY
+    }
+}
+
+impl Default for SummarizationProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SummarizationProcessor {
+    /// Summarizes offline, by picking sentences
+    pub fn new() -> Self {
+        Self {
+            max_bullets: 3,
+            llm: None,
+        }
+    }
+
+    /// Has the model write the summaries. The redacted ticket text is sent.
+    pub fn with_llm(mut self, config: LlmConfig) -> Result<Self, ProcessingError> {
+        self.llm = Some(LlmSummarizer {
+            client: config.client()?,
+            config,
+            prompt_template: PromptTemplate::parse(DEFAULT_PROMPT_TEMPLATE)?,
+            truncation: TruncationConfig::default().with_max_tokens(4000),
+        });
+        Ok(self)
+    }
+
+    /// Most bullet points in a summary, 3 by default
+    pub fn with_max_bullets(mut self, max_bullets: usize) -> Self {
+        self.max_bullets = max_bullets;
+        self
+    }
+
+    /// Picks the sentences sharing the most words with the rest of the ticket, in their
+    /// original order. Local, so it reads the original text. The title is the subject, or
+    /// the top sentence when there is none.
+    pub fn extractive_summary(&self, ticket: &ProcessedTicket) -> TicketSummary {
+        let text = TruncationConfig::default()
+            .apply(&ticket.ticket.content)
+            .text;
+        let sentences = split_sentences(&text);
+        let words: Vec<Vec<String>> = sentences
+            .iter()
+            .map(|sentence| content_words(sentence))
+            .collect();
+        let mut frequencies: HashMap<&str, usize> = HashMap::new();
+        for word in words.iter().flatten() {
+            *frequencies.entry(word.as_str()).or_default() += 1;
+        }
+
+        let mut ranked: Vec<(usize, f32)> = words
+            .iter()
+            .enumerate()
+            .filter(|(_, words)| !words.is_empty())
+            .map(|(i, words)| {
+                let distinct: HashSet<&str> = words.iter().map(String::as_str).collect();
+                let weight: usize = distinct.iter().map(|word| frequencies[word]).sum();
+                (i, weight as f32 / (words.len() as f32).sqrt())
+            })
+            .collect();
+        // Earlier sentences win ties
+        ranked.sort_by(|(i, a), (j, b)| b.total_cmp(a).then(i.cmp(j)));
+
+        let mut picked: Vec<usize> = ranked
+            .iter()
+            .take(self.max_bullets)
+            .map(|(i, _)| *i)
+            .collect();
+        picked.sort();
+        let title = ticket
+            .ticket
+            .subject
+            .as_deref()
+            .map(str::trim)
+            .filter(|subject| !subject.is_empty())
+            .map(str::to_string)
+            .or_else(|| ranked.first().map(|(i, _)| shorten(&sentences[*i])))
+            .unwrap_or_else(|| shorten(text.trim()));
+
+        TicketSummary {
+            title,
+            bullets: picked.into_iter().map(|i| sentences[i].clone()).collect(),
+            extractive: true,
+        }
+    }
+}
+
+impl LlmSummarizer {
+    async fn summarize(
+        &self,
+        ticket: &ProcessedTicket,
+        max_bullets: usize,
+    ) -> Result<TicketSummary, ProcessingError> {
+        let mut values = HashMap::new();
+        values.insert("max_bullets", max_bullets.to_string());
+        let prompt = self.prompt_template.render(&values)?;
+
+        let mut response_schema = serde_json::to_value(schema_for!(OpenAISummaryResponse))
+            .map_err(|e| ProcessingError::SummarizationError(e.to_string()))?;
+        ClassificationProcessor::add_additional_properties_false(&mut response_schema);
+
+        let text = self.truncation.apply(ticket.external_content());
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(self.config.model.as_str())
+            .messages(vec![
+                ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                    content: ChatCompletionRequestSystemMessageContent::Text(prompt),
+                    name: None,
+                }),
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text(delimit_ticket(
+                        ticket.external_subject(),
+                        &text.text,
+                    )),
+                    name: None,
+                }),
+            ])
+            .max_tokens(self.config.max_tokens)
+            .temperature(self.config.temperature)
+            .response_format(ResponseFormat::JsonSchema {
+                json_schema: ResponseFormatJsonSchema {
+                    description: None,
+                    name: "summary".to_string(),
+                    schema: Some(response_schema),
+                    strict: Some(true),
+                },
+            })
+            .build()
+            .map_err(|e| ProcessingError::SummarizationError(e.to_string()))?;
+
+        let response = self
+            .client
+            .chat()
+            .create(request)
+            .await
+            .map_err(|e| ProcessingError::SummarizationError(e.to_string()))?;
+        let summary: OpenAISummaryResponse = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.as_ref())
+            .and_then(|content| serde_json::from_str(content).ok())
+            .ok_or_else(|| {
+                ProcessingError::SummarizationError(
+                    "Failed to parse summarization response".to_string(),
+                )
+            })?;
+
+        Ok(TicketSummary {
+            title: summary.title,
+            bullets: summary.bullets.into_iter().take(max_bullets).collect(),
+            extractive: false,
+        })
+    }
+}
+
+fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let mut start = 0;
+        for end in SENTENCE_END.find_iter(line) {
+            sentences.push(line[start..end.end()].trim().to_string());
+            start = end.end();
+        }
+        if start < line.len() {
+            sentences.push(line[start..].trim().to_string());
+        }
+    }
+    sentences
+}
+
+fn content_words(sentence: &str) -> Vec<String> {
+    WORD.find_iter(&sentence.to_lowercase())
+        .map(|word| word.as_str().to_string())
+        .filter(|word| !STOPWORDS.contains(word.as_str()))
+        .collect()
+}
+
+/// Cuts the text at a word boundary to fit a title
+fn shorten(text: &str) -> String {
+    if text.chars().count() <= MAX_TITLE_CHARS {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(MAX_TITLE_CHARS - 1).collect();
+    let cut = cut.rsplit_once(' ').map_or(cut.as_str(), |(head, _)| head);
+    format!("{}…", cut.trim_end_matches([',', ';', ':', ' ']))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockServer, chat_completion_response};
+    use crate::ticket::SupportTicket;
+    use chrono::Utc;
+
+    const CONTENT: &str = "Hi team,\n\
+        Since the update to version 2.4 the sync client crashes when I upload large files. \
+        I reinstalled the sync client twice, but the upload still crashes after a minute. \
+        The weather here is nice. \
+        Could you tell me how to get large uploads working again?\n\
+        Thanks, Ann\n\n\
+        On Mon, Jan 6, 2025 Support wrote:\n> Did you try restarting the sync client?";
+
+    fn ticket() -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            "t1".to_string(),
+            CONTENT.to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+    }
+
+    #[test]
+    fn test_extractive_summary() {
+        let processor = SummarizationProcessor::new().with_max_bullets(2);
+
+        let summary = processor.extractive_summary(&ticket());
+
+        assert_eq!(
+            summary,
+            TicketSummary {
+                title: "Since the update to version 2.4 the sync client crashes when I upload large…"
+                    .to_string(),
+                bullets: vec![
+                    "Since the update to version 2.4 the sync client crashes when I upload large files."
+                        .to_string(),
+                    "I reinstalled the sync client twice, but the upload still crashes after a minute."
+                        .to_string(),
+                ],
+                extractive: true,
+            }
+        );
+
+        let mut with_subject = ticket();
+        with_subject.ticket = with_subject.ticket.with_subject("Sync crashes on upload");
+        assert_eq!(
+            processor.extractive_summary(&with_subject).title,
+            "Sync crashes on upload"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_llm_summary_with_fallback() {
+        let server = MockServer::start(|request| {
+            let body = String::from_utf8_lossy(&request.body).to_string();
+            let content = if body.contains("version 2.4") {
+                r#"{"title": "Sync client crashes on large uploads", "bullets": ["Crashes since 2.4", "Reinstalling did not help"]}"#
+            } else {
+                "not json"
+            };
+            (200, chat_completion_response(content))
+        })
+        .await;
+        let processor = SummarizationProcessor::new()
+            .with_llm(
+                LlmConfig::default()
+                    .with_api_base(server.url.clone())
+                    .with_api_key("test-key"),
+            )
+            .unwrap();
+
+        let processed = processor.process(ticket()).await;
+        let fallback = processor
+            .process(ProcessedTicket::new(SupportTicket::new(
+                "t2".to_string(),
+                "The invoice total is wrong. It lists three seats instead of two.".to_string(),
+                Utc::now(),
+                "customer1".to_string(),
+            )))
+            .await;
+
+        assert_eq!(
+            processed.summary,
+            ProcessingResult::Success(TicketSummary {
+                title: "Sync client crashes on large uploads".to_string(),
+                bullets: vec![
+                    "Crashes since 2.4".to_string(),
+                    "Reinstalling did not help".to_string()
+                ],
+                extractive: false,
+            })
+        );
+        assert_eq!(processed.prompt_versions[PROMPT_VERSION_KEY], "summary-v1");
+        let ProcessingResult::Success(fallback_summary) = &fallback.summary else {
+            panic!("Expected a summary, got {:?}", fallback.summary);
+        };
+        assert!(fallback_summary.extractive);
+        assert!(!fallback.prompt_versions.contains_key(PROMPT_VERSION_KEY));
+    }
+}
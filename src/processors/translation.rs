@@ -0,0 +1,496 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_openai::{Client, config::OpenAIConfig, types::*};
+use language_enum::Language;
+use schemars::{JsonSchema, schema_for};
+use serde::Deserialize;
+
+use crate::{
+    error::ProcessingError,
+    llm::LlmConfig,
+    pipeline::{FieldMask, TicketProcessor},
+    processors::{classification::ClassificationProcessor, language::language_name},
+    prompt::{PromptTemplate, delimit_ticket},
+    ticket::{ProcessedTicket, ProcessingResult, Translation},
+    truncation::CHARS_PER_TOKEN,
+};
+use async_trait::async_trait;
+use log::info;
+
+/// The built-in prompt
+const DEFAULT_PROMPT_TEMPLATE: &str = include_str!("../../prompts/translation.prompt");
+
+/// Key under which the prompt version is recorded in `ProcessedTicket::prompt_versions`
+const PROMPT_VERSION_KEY: &str = "translation";
+
+/// Default size of the pieces long tickets are translated in, leaving room in the response for
+/// the translation
+const DEFAULT_MAX_CHUNK_TOKENS: usize = 1500;
+
+/// Translates tickets into English
+#[async_trait]
+pub trait Translator: Send + Sync {
+    async fn translate(
+        &self,
+        subject: Option<&str>,
+        content: &str,
+        language: &Language,
+    ) -> Result<Translation, ProcessingError>;
+
+    /// Version of the prompt used, for translators backed by an LLM
+    fn prompt_version(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Translates with the configured OpenAI-compatible model
+pub struct OpenAITranslator {
+    client: Client<OpenAIConfig>,
+    config: LlmConfig,
+    prompt_template: PromptTemplate,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct OpenAITranslationResponse {
+    subject: String,
+    content: String,
+}
+
+impl OpenAITranslator {
+    pub fn new(config: LlmConfig) -> Result<Self, ProcessingError> {
+        Ok(Self {
+            client: config.client()?,
+            config,
+            prompt_template: PromptTemplate::parse(DEFAULT_PROMPT_TEMPLATE)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Translator for OpenAITranslator {
+    async fn translate(
+        &self,
+        subject: Option<&str>,
+        content: &str,
+        language: &Language,
+    ) -> Result<Translation, ProcessingError> {
+        let mut values = HashMap::new();
+        values.insert("language", language_name(language));
+        let prompt = self.prompt_template.render(&values)?;
+
+        let mut response_schema = serde_json::to_value(schema_for!(OpenAITranslationResponse))
+            .map_err(|e| ProcessingError::TranslationError(e.to_string()))?;
+        ClassificationProcessor::add_additional_properties_false(&mut response_schema);
+
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(self.config.model.as_str())
+            .messages(vec![
+                ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                    content: ChatCompletionRequestSystemMessageContent::Text(prompt),
+                    name: None,
+                }),
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text(delimit_ticket(
+                        subject, content,
+                    )),
+                    name: None,
+                }),
+            ])
+            .max_tokens(self.config.max_tokens)
+            .temperature(self.config.temperature)
+            .response_format(ResponseFormat::JsonSchema {
+                json_schema: ResponseFormatJsonSchema {
+                    description: None,
+                    name: "translation".to_string(),
+                    schema: Some(response_schema),
+                    strict: Some(true),
+                },
+            })
+            .build()
+            .map_err(|e| ProcessingError::TranslationError(e.to_string()))?;
+
+        let response = self
+            .client
+            .chat()
+            .create(request)
+            .await
+            .map_err(|e| ProcessingError::TranslationError(e.to_string()))?;
+        let translation: OpenAITranslationResponse = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.as_ref())
+            .and_then(|content| serde_json::from_str(content).ok())
+            .ok_or_else(|| {
+                ProcessingError::TranslationError(
+                    "Failed to parse translation response".to_string(),
+                )
+            })?;
+
+        Ok(Translation {
+            source_language: language.clone(),
+            subject: subject.map(|_| translation.subject),
+            content: translation.content,
+        })
+    }
+
+    fn prompt_version(&self) -> Option<&str> {
+        Some(&self.prompt_template.version)
+    }
+}
+
+/// Translates non-English tickets into English for processors with English-only rules, which
+/// opt in with `ContentSource::Translated`. The redacted text is translated, as it may be sent
+/// to an external service.
+///
+/// Nothing is left out, as quoted replies and signatures may matter to those rules; long
+/// tickets are translated in chunks split at paragraph, line or word boundaries.
+pub struct TranslationProcessor {
+    translator: Arc<dyn Translator>,
+    max_chunk_chars: usize,
+}
+
+#[async_trait]
+impl TicketProcessor for TranslationProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "TranslationProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        if let Some(reason) = ticket.skip_reason() {
+            info!(
+                "TranslationProcessor skipped ticket {}: {}",
+                ticket_id, reason
+            );
+            return ticket.with_translation(ProcessingResult::Skipped(reason));
+        }
+        let language = match &ticket.language {
+            ProcessingResult::Success(Language::English) => {
+                return ticket.with_translation(ProcessingResult::Success(None));
+            }
+            ProcessingResult::Success(language) => language.clone(),
+            _ => {
+                return ticket
+                    .with_translation(ProcessingResult::Skipped("Unknown language".to_string()));
+            }
+        };
+        let result = match self
+            .translate_in_chunks(
+                ticket.external_subject(),
+                ticket.external_content(),
+                &language,
+            )
+            .await
+        {
+            Ok(translation) => {
+                let version = self.translator.prompt_version().map(str::to_string);
+                let result = ticket.with_translation(ProcessingResult::Success(Some(translation)));
+                match version {
+                    Some(version) => result.with_prompt_version(PROMPT_VERSION_KEY, &version),
+                    None => result,
+                }
+            }
+            Err(e) => ticket.with_translation(ProcessingResult::Error(e)),
+        };
+
+        info!(
+            "TranslationProcessor finished processing ticket: {}",
+            ticket_id
+        );
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        FieldMask::LANGUAGE
+    }
+
+    fn optional_fields(&self) -> FieldMask {
+        FieldMask::REDACTION | FieldMask::MESSAGE_KIND
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::TRANSLATION
+    }
+}
+
+impl TranslationProcessor {
+    pub fn new(translator: Arc<dyn Translator>) -> Self {
+        Self {
+            translator,
+            max_chunk_chars: DEFAULT_MAX_CHUNK_TOKENS * CHARS_PER_TOKEN,
+        }
+    }
+
+    /// Translates with the configured OpenAI-compatible model
+    pub fn with_openai(config: LlmConfig) -> Result<Self, ProcessingError> {
+        Ok(Self::new(Arc::new(OpenAITranslator::new(config)?)))
+    }
+
+    /// Size of the pieces long tickets are translated in, estimated with `CHARS_PER_TOKEN`
+    /// characters per token
+    pub fn with_max_chunk_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_chunk_chars = max_tokens.max(1) * CHARS_PER_TOKEN;
+        self
+    }
+
+    /// Translates the content chunk by chunk, the subject along with the first chunk
+    async fn translate_in_chunks(
+        &self,
+        subject: Option<&str>,
+        content: &str,
+        language: &Language,
+    ) -> Result<Translation, ProcessingError> {
+        let mut translation = Translation {
+            source_language: language.clone(),
+            subject: None,
+            content: String::new(),
+        };
+        for (i, chunk) in split_into_chunks(content, self.max_chunk_chars)
+            .into_iter()
+            .enumerate()
+        {
+            let part = self
+                .translator
+                .translate(if i == 0 { subject } else { None }, chunk, language)
+                .await?;
+            if i == 0 {
+                translation.subject = part.subject;
+            }
+            // Keep the paragraph or line break the chunk ended with
+            let separator = &chunk[chunk.trim_end().len()..];
+            translation.content.push_str(part.content.trim_end());
+            translation.content.push_str(separator);
+        }
+        translation
+            .content
+            .truncate(translation.content.trim_end().len());
+        Ok(translation)
+    }
+}
+
+/// Splits the text into pieces of at most `max_chars` characters, preferably after a blank
+/// line, then after a line break, then after a space. The pieces add up to the text.
+fn split_into_chunks(text: &str, max_chars: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while let Some((limit, _)) = rest.char_indices().nth(max_chars) {
+        let head = &rest[..limit];
+        let end = [
+            head.rfind("\n\n").map(|i| i + 2),
+            head.rfind('\n').map(|i| i + 1),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(head.rfind(' ').map(|i| i + 1))
+        .find(|&end| end > 0)
+        .unwrap_or(limit);
+        chunks.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() || chunks.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::urgency::{UrgencyProcessor, UrgencyRule};
+    use crate::test_util::{MockServer, chat_completion_response};
+    use crate::ticket::{
+        ContentSource, RedactedContent, SupportTicket, TicketPriority, UrgencyEffect,
+    };
+    use chrono::Utc;
+    use std::sync::Mutex;
+
+    /// Returns canned translations. Subjects without a canned translation are kept as they
+    /// are, content without one is an error.
+    #[derive(Default)]
+    struct MockTranslator {
+        translations: HashMap<String, String>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl MockTranslator {
+        fn new() -> Self {
+            Self::default()
+        }
+
+        fn with_translation(mut self, text: &str, english: &str) -> Self {
+            self.translations
+                .insert(text.to_string(), english.to_string());
+            self
+        }
+
+        /// The content of every ticket translated so far
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Translator for MockTranslator {
+        async fn translate(
+            &self,
+            subject: Option<&str>,
+            content: &str,
+            language: &Language,
+        ) -> Result<Translation, ProcessingError> {
+            self.calls.lock().unwrap().push(content.to_string());
+            let content = self.translations.get(content).ok_or_else(|| {
+                ProcessingError::TranslationError(format!("No translation for \"{}\"", content))
+            })?;
+            Ok(Translation {
+                source_language: language.clone(),
+                subject: subject.map(|subject| {
+                    self.translations
+                        .get(subject)
+                        .map_or(subject, String::as_str)
+                        .to_string()
+                }),
+                content: content.clone(),
+            })
+        }
+    }
+
+    fn ticket(id: &str, content: &str, language: Language) -> ProcessedTicket {
+        ProcessedTicket::new(SupportTicket::new(
+            id.to_string(),
+            content.to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        ))
+        .with_language(ProcessingResult::Success(language))
+    }
+
+    #[tokio::test]
+    async fn test_translates_non_english_tickets_for_english_rules() {
+        let spanish = "Nuestro sitio web está caído desde esta mañana.";
+        let translator = Arc::new(
+            MockTranslator::new().with_translation(spanish, "The site is down since this morning."),
+        );
+        let processor = TranslationProcessor::new(translator.clone());
+        let urgency = UrgencyProcessor::with_rules(vec![UrgencyRule::new(
+            "outage",
+            &["site is down"],
+            UrgencyEffect::MinimumPriority(TicketPriority::Critical),
+        )])
+        .with_content_source(ContentSource::Translated);
+
+        let translated = processor
+            .process(ticket("t1", spanish, Language::Spanish))
+            .await;
+        let english = processor
+            .process(ticket("t2", "The site is down.", Language::English))
+            .await;
+
+        assert_eq!(
+            translated.translation,
+            ProcessingResult::Success(Some(Translation {
+                source_language: Language::Spanish,
+                subject: None,
+                content: "The site is down since this morning.".to_string(),
+            }))
+        );
+        assert_eq!(translated.ticket.content, spanish);
+        assert_eq!(english.translation, ProcessingResult::Success(None));
+        assert_eq!(translator.calls(), vec![spanish.to_string()]);
+        assert!(urgency.required_fields().contains(FieldMask::TRANSLATION));
+        let ProcessingResult::Success(signals) = urgency.process(translated).await.urgency else {
+            panic!("Expected urgency signals");
+        };
+        assert_eq!(signals[0].phrase, "site is down");
+    }
+
+    #[tokio::test]
+    async fn test_long_tickets_are_translated_in_full() {
+        let reply = "Am 6. Januar schrieb Support:\n> Bitte starten Sie neu.";
+        let translator = Arc::new(
+            MockTranslator::new()
+                .with_translation(
+                    "Die Seite ist seit heute Morgen nicht erreichbar.\n\n",
+                    "The site has been down since this morning.",
+                )
+                .with_translation(reply, "On January 6 Support wrote:\n> Please restart."),
+        );
+        let processor = TranslationProcessor::new(translator.clone()).with_max_chunk_tokens(15);
+        let content = format!(
+            "Die Seite ist seit heute Morgen nicht erreichbar.\n\n{}",
+            reply
+        );
+
+        let processed = processor
+            .process(ticket("t1", &content, Language::German))
+            .await;
+
+        let ProcessingResult::Success(Some(translation)) = processed.translation else {
+            panic!("Expected a translation, got {:?}", processed.translation);
+        };
+        assert_eq!(
+            translation.content,
+            "The site has been down since this morning.\n\nOn January 6 Support wrote:\n> Please restart."
+        );
+        assert_eq!(translator.calls().len(), 2);
+        assert_eq!(
+            split_into_chunks("one two three", 8),
+            vec!["one two ", "three"]
+        );
+        assert_eq!(split_into_chunks("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(split_into_chunks("", 3), vec![""]);
+    }
+
+    #[tokio::test]
+    async fn test_openai_translator() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                chat_completion_response(
+                    r#"{"subject": "", "content": "My invoice is wrong, write to [EMAIL]."}"#,
+                ),
+            )
+        })
+        .await;
+        let processor = TranslationProcessor::with_openai(
+            LlmConfig::default()
+                .with_api_base(server.url.clone())
+                .with_api_key("test-key"),
+        )
+        .unwrap();
+        let ticket = ticket(
+            "t1",
+            "Meine Rechnung ist falsch, schreiben Sie an ann@example.com.",
+            Language::German,
+        )
+        .with_redaction(ProcessingResult::Success(RedactedContent {
+            subject: None,
+            content: "Meine Rechnung ist falsch, schreiben Sie an [EMAIL].".to_string(),
+            pii_types: vec![],
+        }));
+
+        let processed = processor.process(ticket).await;
+
+        let ProcessingResult::Success(Some(translation)) = &processed.translation else {
+            panic!("Expected a translation, got {:?}", processed.translation);
+        };
+        assert_eq!(
+            translation.content,
+            "My invoice is wrong, write to [EMAIL]."
+        );
+        assert_eq!(
+            processed.prompt_versions[PROMPT_VERSION_KEY],
+            "translation-v1"
+        );
+        let request = server.requests()[0].json();
+        let messages = request["messages"].as_array().unwrap();
+        assert!(
+            messages[0]["content"]
+                .as_str()
+                .unwrap()
+                .contains("from German into English")
+        );
+        let user = messages[1]["content"].as_str().unwrap();
+        assert!(user.contains("[EMAIL]") && !user.contains("ann@example.com"));
+    }
+}
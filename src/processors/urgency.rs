@@ -0,0 +1,359 @@
+use std::path::Path;
+
+use crate::{
+    error::ProcessingError,
+    pipeline::{FieldMask, TicketProcessor},
+    ticket::{
+        ContentSource, ProcessedTicket, ProcessingResult, TicketPriority, UrgencyEffect,
+        UrgencySignal,
+    },
+};
+use async_trait::async_trait;
+use log::info;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// A group of phrases that escalate a ticket in the same way
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct UrgencyRule {
+    pub kind: String,
+    /// Matched as whole words, ignoring case and differences in whitespace
+    pub phrases: Vec<String>,
+    pub effect: UrgencyEffect,
+}
+
+impl UrgencyRule {
+    pub fn new(kind: &str, phrases: &[&str], effect: UrgencyEffect) -> Self {
+        Self {
+            kind: kind.to_string(),
+            phrases: phrases.iter().map(|phrase| phrase.to_string()).collect(),
+            effect,
+        }
+    }
+
+    /// Built-in rules in English, Spanish, German and French
+    pub fn defaults() -> Vec<Self> {
+        use TicketPriority::{Critical, High};
+        use UrgencyEffect::{Boost, MinimumPriority};
+        vec![
+            Self::new(
+                "outage",
+                &[
+                    "production down",
+                    "production is down",
+                    "prod is down",
+                    "site is down",
+                    "system is down",
+                    "service is down",
+                    "producción caída",
+                    "caída del servicio",
+                    "servicio caído",
+                    "produktion steht",
+                    "system ausgefallen",
+                    "totalausfall",
+                    "production en panne",
+                    "panne générale",
+                    "service en panne",
+                ],
+                MinimumPriority(Critical),
+            ),
+            // Also used for past or planned outages, so it only raises the score
+            Self::new(
+                "outage_mention",
+                &["outage", "ausfall", "interrupción"],
+                Boost(1.2),
+            ),
+            Self::new(
+                "data_loss",
+                &[
+                    "data loss",
+                    "lost all our data",
+                    "lost all my data",
+                    "data was deleted",
+                    "data corruption",
+                    "pérdida de datos",
+                    "datenverlust",
+                    "perte de données",
+                ],
+                MinimumPriority(Critical),
+            ),
+            Self::new(
+                "security",
+                &[
+                    "security breach",
+                    "data breach",
+                    "account was hacked",
+                    "account compromised",
+                    "brecha de seguridad",
+                    "sicherheitsvorfall",
+                    "faille de sécurité",
+                ],
+                MinimumPriority(Critical),
+            ),
+            Self::new(
+                "legal",
+                &[
+                    "legal action",
+                    "lawsuit",
+                    "my lawyer",
+                    "my attorney",
+                    "acciones legales",
+                    "mi abogado",
+                    "rechtliche schritte",
+                    "meinen anwalt",
+                    "action en justice",
+                    "mon avocat",
+                ],
+                MinimumPriority(High),
+            ),
+            Self::new(
+                "chargeback",
+                &[
+                    "chargeback",
+                    "dispute the charge",
+                    "contracargo",
+                    "rückbuchung",
+                    "rétrofacturation",
+                ],
+                MinimumPriority(High),
+            ),
+            Self::new(
+                "churn",
+                &[
+                    "cancel my subscription",
+                    "cancel my account",
+                    "close my account",
+                    "cancelar mi suscripción",
+                    "cancelar mi cuenta",
+                    "abo kündigen",
+                    "vertrag kündigen",
+                    "résilier mon abonnement",
+                ],
+                Boost(1.3),
+            ),
+        ]
+    }
+}
+
+/// Looks for escalation phrases such as "production down" or "chargeback" and emits urgency
+/// signals, which `PriorityProcessor` applies as boosts or minimum priorities whatever the
+/// sentiment of the ticket.
+pub struct UrgencyProcessor {
+    rules: Vec<(UrgencyRule, Vec<(String, Regex)>)>,
+    content_source: ContentSource,
+}
+
+#[async_trait]
+impl TicketProcessor for UrgencyProcessor {
+    async fn process(&self, ticket: ProcessedTicket) -> ProcessedTicket {
+        info!(
+            "UrgencyProcessor received event for ticket: {}",
+            ticket.ticket.id
+        );
+
+        let ticket_id = ticket.ticket.id.clone();
+        let signals = self.detect(&ticket.text(self.content_source));
+        let result = ticket.with_urgency(ProcessingResult::Success(signals));
+
+        info!("UrgencyProcessor finished processing ticket: {}", ticket_id);
+        result
+    }
+
+    fn required_fields(&self) -> FieldMask {
+        match self.content_source {
+            ContentSource::Original => FieldMask::empty(),
+            ContentSource::Translated => FieldMask::TRANSLATION,
+        }
+    }
+
+    fn output_fields(&self) -> FieldMask {
+        FieldMask::URGENCY
+    }
+}
+
+impl Default for UrgencyProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UrgencyProcessor {
+    pub fn new() -> Self {
+        Self::with_rules(UrgencyRule::defaults())
+    }
+
+    /// Blank phrases are ignored, as they would match every ticket
+    pub fn with_rules(rules: Vec<UrgencyRule>) -> Self {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let patterns = rule
+                    .phrases
+                    .iter()
+                    .map(|phrase| phrase.trim())
+                    .filter(|phrase| !phrase.is_empty())
+                    .map(|phrase| (phrase.to_string(), phrase_pattern(phrase)))
+                    .collect();
+                (rule, patterns)
+            })
+            .collect();
+        Self {
+            rules,
+            content_source: ContentSource::Original,
+        }
+    }
+
+    /// Whether to match the rules against the original text or its English translation,
+    /// for English-only rules. The original by default.
+    pub fn with_content_source(mut self, content_source: ContentSource) -> Self {
+        self.content_source = content_source;
+        self
+    }
+
+    /// Loads the rules from a JSON file holding a list of rules, e.g.
+    /// `[{"kind": "outage", "phrases": ["site down"], "effect": {"MinimumPriority": "Critical"}}]`.
+    /// Fails if a rule has no phrases that are not blank.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ProcessingError> {
+        let path = path.as_ref();
+        let rules: Vec<UrgencyRule> = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                ProcessingError::ConfigError(format!(
+                    "Failed to load urgency rules {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        if let Some(rule) = rules
+            .iter()
+            .find(|rule| rule.phrases.iter().all(|phrase| phrase.trim().is_empty()))
+        {
+            return Err(ProcessingError::ConfigError(format!(
+                "Urgency rule {} in {} has no phrases",
+                rule.kind,
+                path.display()
+            )));
+        }
+        Ok(Self::with_rules(rules))
+    }
+
+    /// Returns one signal for each rule with a matching phrase
+    pub fn detect(&self, text: &str) -> Vec<UrgencySignal> {
+        self.rules
+            .iter()
+            .filter_map(|(rule, patterns)| {
+                patterns
+                    .iter()
+                    .find(|(_, pattern)| pattern.is_match(text))
+                    .map(|(phrase, _)| UrgencySignal {
+                        kind: rule.kind.clone(),
+                        phrase: phrase.clone(),
+                        effect: rule.effect.clone(),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Matches the phrase as whole words, ignoring case and differences in whitespace
+pub(crate) fn phrase_pattern(phrase: &str) -> Regex {
+    let words: Vec<String> = phrase.split_whitespace().map(regex::escape).collect();
+    RegexBuilder::new(&format!(r"\b{}\b", words.join(r"\s+")))
+        .case_insensitive(true)
+        .build()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_multilingual_phrases() {
+        let processor = UrgencyProcessor::new();
+
+        let signals = processor.detect(
+            "Hi, sorry to bother you, but our PRODUCTION is\ndown. If this continues I will cancel my subscription.",
+        );
+        assert_eq!(
+            signals,
+            vec![
+                UrgencySignal {
+                    kind: "outage".to_string(),
+                    phrase: "production is down".to_string(),
+                    effect: UrgencyEffect::MinimumPriority(TicketPriority::Critical),
+                },
+                UrgencySignal {
+                    kind: "churn".to_string(),
+                    phrase: "cancel my subscription".to_string(),
+                    effect: UrgencyEffect::Boost(1.3),
+                },
+            ]
+        );
+
+        let kinds = |text: &str| -> Vec<String> {
+            processor
+                .detect(text)
+                .into_iter()
+                .map(|signal| signal.kind)
+                .collect()
+        };
+        assert_eq!(kinds("Tenemos una pérdida de datos"), vec!["data_loss"]);
+        assert_eq!(kinds("Ich leite rechtliche Schritte ein"), vec!["legal"]);
+        assert_eq!(kinds("Je demande une rétrofacturation"), vec!["chargeback"]);
+        assert!(kinds("The layout looks down-to-earth, thanks!").is_empty());
+        // Phrases match whole words only
+        assert!(kinds("Check the outages page").is_empty());
+        // Only an ongoing outage is critical
+        let signals = processor.detect("Will last night's outage be credited on my invoice?");
+        assert_eq!(signals.len(), 1);
+        assert_eq!(signals[0].kind, "outage_mention");
+        assert_eq!(signals[0].effect, UrgencyEffect::Boost(1.2));
+    }
+
+    #[test]
+    fn test_rules_from_file() {
+        let path = std::env::temp_dir().join(format!("urgency-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[{"kind": "vip", "phrases": ["board meeting"], "effect": {"Boost": 1.5}}]"#,
+        )
+        .unwrap();
+        let processor = UrgencyProcessor::from_file(&path).unwrap();
+        std::fs::write(&path, "not json").unwrap();
+        assert!(UrgencyProcessor::from_file(&path).is_err());
+        // A blank phrase would match every ticket
+        std::fs::write(
+            &path,
+            r#"[{"kind": "vip", "phrases": [" "], "effect": {"MinimumPriority": "Critical"}}]"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            UrgencyProcessor::from_file(&path),
+            Err(ProcessingError::ConfigError(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            processor.detect("Needed for the Board  Meeting"),
+            vec![UrgencySignal {
+                kind: "vip".to_string(),
+                phrase: "board meeting".to_string(),
+                effect: UrgencyEffect::Boost(1.5),
+            }]
+        );
+        assert!(processor.detect("production is down").is_empty());
+
+        let processor = UrgencyProcessor::with_rules(vec![UrgencyRule::new(
+            "vip",
+            &["", " board meeting "],
+            UrgencyEffect::Boost(1.5),
+        )]);
+        assert!(processor.detect("Where is my invoice?").is_empty());
+        assert_eq!(
+            processor.detect("for the board meeting")[0].phrase,
+            "board meeting"
+        );
+    }
+}
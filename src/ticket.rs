@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use language_enum::Language;
 use schemars::JsonSchema;
@@ -8,20 +10,47 @@ use crate::error::ProcessingError;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SupportTicket {
     pub id: String,
+    #[serde(default)]
+    pub subject: Option<String>,
     pub content: String,
     pub timestamp: DateTime<Utc>,
     pub customer_id: String,
+    /// Support tier of the customer, e.g. "enterprise", used for routing
+    #[serde(default)]
+    pub customer_tier: Option<String>,
+    /// Locale the customer chose, e.g. "es-MX", used when the language of a ticket cannot be
+    /// detected reliably
+    #[serde(default)]
+    pub customer_locale: Option<String>,
 }
 
 impl SupportTicket {
     pub fn new(id: String, content: String, timestamp: DateTime<Utc>, customer_id: String) -> Self {
         SupportTicket {
             id,
+            subject: None,
             content,
             timestamp,
             customer_id,
+            customer_tier: None,
+            customer_locale: None,
         }
     }
+
+    pub fn with_subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    pub fn with_customer_tier(mut self, customer_tier: impl Into<String>) -> Self {
+        self.customer_tier = Some(customer_tier.into());
+        self
+    }
+
+    pub fn with_customer_locale(mut self, customer_locale: impl Into<String>) -> Self {
+        self.customer_locale = Some(customer_locale.into());
+        self
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -29,15 +58,47 @@ pub enum ProcessingResult<T> {
     Processing,
     Success(T),
     Error(ProcessingError),
+    /// The processor did not run on this ticket, for the given reason
+    Skipped(String),
+}
+
+impl<T> ProcessingResult<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> ProcessingResult<U> {
+        match self {
+            ProcessingResult::Processing => ProcessingResult::Processing,
+            ProcessingResult::Success(value) => ProcessingResult::Success(f(value)),
+            ProcessingResult::Error(err) => ProcessingResult::Error(err),
+            ProcessingResult::Skipped(reason) => ProcessingResult::Skipped(reason),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessedTicket {
     pub ticket: SupportTicket,
     pub language: ProcessingResult<Language>,
+    /// How `language` was determined
+    pub language_detection: ProcessingResult<LanguageDetection>,
     pub sentiment: ProcessingResult<SentimentScore>,
     pub category: ProcessingResult<TicketCategory>,
     pub priority: ProcessingResult<TicketPriority>,
+    pub redaction: ProcessingResult<RedactedContent>,
+    pub entities: ProcessingResult<TicketEntities>,
+    pub urgency: ProcessingResult<Vec<UrgencySignal>>,
+    pub churn_risk: ProcessingResult<ChurnRisk>,
+    pub message_kind: ProcessingResult<MessageKind>,
+    /// The earlier ticket this one duplicates, or `None` if it is not a duplicate
+    pub duplicate: ProcessingResult<Option<DuplicateLink>>,
+    pub routing: ProcessingResult<Routing>,
+    /// Suggested reply, or `None` if no reply template fits the ticket
+    pub reply_draft: ProcessingResult<Option<ReplyDraft>>,
+    pub summary: ProcessingResult<TicketSummary>,
+    /// English translation, or `None` if the ticket is in English already
+    pub translation: ProcessingResult<Option<Translation>>,
+    pub review_signals: Vec<ReviewSignal>,
+    /// Version of the prompt template used by each LLM-based processor, keyed by processor
+    #[serde(default)]
+    pub prompt_versions: BTreeMap<String, String>,
 }
 
 impl ProcessedTicket {
@@ -45,15 +106,35 @@ impl ProcessedTicket {
         ProcessedTicket {
             ticket,
             language: ProcessingResult::Processing,
+            language_detection: ProcessingResult::Processing,
             sentiment: ProcessingResult::Processing,
             category: ProcessingResult::Processing,
             priority: ProcessingResult::Processing,
+            redaction: ProcessingResult::Processing,
+            entities: ProcessingResult::Processing,
+            urgency: ProcessingResult::Processing,
+            churn_risk: ProcessingResult::Processing,
+            message_kind: ProcessingResult::Processing,
+            duplicate: ProcessingResult::Processing,
+            routing: ProcessingResult::Processing,
+            reply_draft: ProcessingResult::Processing,
+            summary: ProcessingResult::Processing,
+            translation: ProcessingResult::Processing,
+            review_signals: Vec::new(),
+            prompt_versions: BTreeMap::new(),
         }
     }
     pub fn with_language(mut self, language: ProcessingResult<Language>) -> Self {
         self.language = language;
         self
     }
+    pub fn with_language_detection(
+        mut self,
+        language_detection: ProcessingResult<LanguageDetection>,
+    ) -> Self {
+        self.language_detection = language_detection;
+        self
+    }
     pub fn with_sentiment(mut self, sentiment: ProcessingResult<SentimentScore>) -> Self {
         self.sentiment = sentiment;
         self
@@ -66,6 +147,101 @@ impl ProcessedTicket {
         self.priority = priority;
         self
     }
+    pub fn with_redaction(mut self, redaction: ProcessingResult<RedactedContent>) -> Self {
+        self.redaction = redaction;
+        self
+    }
+    pub fn with_entities(mut self, entities: ProcessingResult<TicketEntities>) -> Self {
+        self.entities = entities;
+        self
+    }
+    pub fn with_urgency(mut self, urgency: ProcessingResult<Vec<UrgencySignal>>) -> Self {
+        self.urgency = urgency;
+        self
+    }
+    pub fn with_churn_risk(mut self, churn_risk: ProcessingResult<ChurnRisk>) -> Self {
+        self.churn_risk = churn_risk;
+        self
+    }
+    pub fn with_message_kind(mut self, message_kind: ProcessingResult<MessageKind>) -> Self {
+        self.message_kind = message_kind;
+        self
+    }
+    pub fn with_duplicate(mut self, duplicate: ProcessingResult<Option<DuplicateLink>>) -> Self {
+        self.duplicate = duplicate;
+        self
+    }
+    pub fn with_routing(mut self, routing: ProcessingResult<Routing>) -> Self {
+        self.routing = routing;
+        self
+    }
+    pub fn with_reply_draft(mut self, reply_draft: ProcessingResult<Option<ReplyDraft>>) -> Self {
+        self.reply_draft = reply_draft;
+        self
+    }
+    pub fn with_summary(mut self, summary: ProcessingResult<TicketSummary>) -> Self {
+        self.summary = summary;
+        self
+    }
+    pub fn with_translation(mut self, translation: ProcessingResult<Option<Translation>>) -> Self {
+        self.translation = translation;
+        self
+    }
+    pub fn with_review_signal(mut self, signal: ReviewSignal) -> Self {
+        if !self.review_signals.contains(&signal) {
+            self.review_signals.push(signal);
+        }
+        self
+    }
+
+    pub fn with_prompt_version(mut self, key: &str, version: &str) -> Self {
+        self.prompt_versions
+            .insert(key.to_string(), version.to_string());
+        self
+    }
+
+    /// Why expensive processing should be skipped, if the ticket is not a genuine customer
+    /// message but e.g. an out-of-office reply or spam
+    pub fn skip_reason(&self) -> Option<String> {
+        match &self.message_kind {
+            ProcessingResult::Success(kind) if *kind != MessageKind::Genuine => {
+                Some(format!("{} message", kind.description()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The content to send to external services: the redacted content once redaction has run,
+    /// the original content otherwise
+    pub fn external_content(&self) -> &str {
+        match &self.redaction {
+            ProcessingResult::Success(redacted) => &redacted.content,
+            _ => &self.ticket.content,
+        }
+    }
+
+    /// The subject to send to external services, redacted like `external_content`
+    pub fn external_subject(&self) -> Option<&str> {
+        match &self.redaction {
+            ProcessingResult::Success(redacted) => redacted.subject.as_deref(),
+            _ => self.ticket.subject.as_deref(),
+        }
+    }
+
+    /// The subject and content to read, one after the other. The translated text is the
+    /// original text when there is no translation, e.g. because the ticket is in English.
+    pub fn text(&self, source: ContentSource) -> String {
+        let (subject, content) = match (source, &self.translation) {
+            (ContentSource::Translated, ProcessingResult::Success(Some(translation))) => {
+                (translation.subject.as_deref(), translation.content.as_str())
+            }
+            _ => (self.ticket.subject.as_deref(), self.ticket.content.as_str()),
+        };
+        match subject {
+            Some(subject) => format!("{}\n{}", subject, content),
+            None => content.to_string(),
+        }
+    }
 
     pub fn merge_from(&mut self, other: Self) {
         match other.language {
@@ -73,6 +249,11 @@ impl ProcessedTicket {
             _ => self.language = other.language,
         }
 
+        match other.language_detection {
+            ProcessingResult::Processing => {}
+            _ => self.language_detection = other.language_detection,
+        }
+
         match other.sentiment {
             ProcessingResult::Processing => {}
             _ => self.sentiment = other.sentiment,
@@ -87,6 +268,105 @@ impl ProcessedTicket {
             ProcessingResult::Processing => {}
             _ => self.priority = other.priority,
         }
+
+        match other.redaction {
+            ProcessingResult::Processing => {}
+            _ => self.redaction = other.redaction,
+        }
+
+        match other.entities {
+            ProcessingResult::Processing => {}
+            _ => self.entities = other.entities,
+        }
+
+        match other.urgency {
+            ProcessingResult::Processing => {}
+            _ => self.urgency = other.urgency,
+        }
+
+        match other.churn_risk {
+            ProcessingResult::Processing => {}
+            _ => self.churn_risk = other.churn_risk,
+        }
+
+        match other.message_kind {
+            ProcessingResult::Processing => {}
+            _ => self.message_kind = other.message_kind,
+        }
+
+        match other.duplicate {
+            ProcessingResult::Processing => {}
+            _ => self.duplicate = other.duplicate,
+        }
+
+        match other.routing {
+            ProcessingResult::Processing => {}
+            _ => self.routing = other.routing,
+        }
+
+        match other.reply_draft {
+            ProcessingResult::Processing => {}
+            _ => self.reply_draft = other.reply_draft,
+        }
+
+        match other.summary {
+            ProcessingResult::Processing => {}
+            _ => self.summary = other.summary,
+        }
+
+        match other.translation {
+            ProcessingResult::Processing => {}
+            _ => self.translation = other.translation,
+        }
+
+        for signal in other.review_signals {
+            if !self.review_signals.contains(&signal) {
+                self.review_signals.push(signal);
+            }
+        }
+
+        self.prompt_versions.extend(other.prompt_versions);
+    }
+}
+
+/// Kinds of personal data removed by redaction
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PiiType {
+    Email,
+    PhoneNumber,
+    CreditCard,
+    Iban,
+    PostalAddress,
+}
+
+/// Ticket text with personal data replaced by placeholders such as `[EMAIL]`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RedactedContent {
+    pub subject: Option<String>,
+    pub content: String,
+    /// Kinds of personal data that were found and removed, for compliance records
+    pub pii_types: Vec<PiiType>,
+}
+
+/// Structured data found in the ticket text, each list in order of first appearance
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TicketEntities {
+    pub order_ids: Vec<String>,
+    pub emails: Vec<String>,
+    pub urls: Vec<String>,
+    /// Names from the product catalog, as spelled in the catalog
+    pub products: Vec<String>,
+    pub versions: Vec<String>,
+    /// One line per stack trace naming the error and where it was raised, e.g.
+    /// `java.lang.NullPointerException at com.acme.Cart.total`
+    pub stack_traces: Vec<String>,
+    pub http_status_codes: Vec<u16>,
+}
+
+impl TicketEntities {
+    /// Whether the ticket reports a server error or a crash
+    pub fn has_server_errors(&self) -> bool {
+        !self.stack_traces.is_empty() || self.http_status_codes.iter().any(|code| *code >= 500)
     }
 }
 
@@ -94,15 +374,59 @@ impl ProcessedTicket {
 pub struct SentimentScore {
     pub label: SentimentLabel,
     pub confidence: f32, // A value between 0.0 and 1.0 indicating the confidence of the sentiment score
+    /// Score for each label returned by the model, highest first
+    #[serde(default)]
+    pub distribution: Vec<(SentimentLabel, f32)>,
+    /// Expected polarity over the distribution, from -1.0 (very negative) to 1.0 (very positive)
+    #[serde(default)]
+    pub polarity: f32,
 }
 
 impl SentimentScore {
     pub fn new(label: SentimentLabel, confidence: f32) -> Self {
-        SentimentScore { label, confidence }
+        SentimentScore {
+            polarity: label.polarity(),
+            distribution: vec![(label.clone(), confidence)],
+            label,
+            confidence,
+        }
+    }
+
+    /// Builds a score from the probability of each label. Scores for repeated labels are added
+    /// together. The most likely label becomes the label of the score. Returns `None` if the
+    /// distribution is empty.
+    pub fn from_distribution(distribution: Vec<(SentimentLabel, f32)>) -> Option<Self> {
+        let mut merged: Vec<(SentimentLabel, f32)> = Vec::new();
+        for (label, score) in distribution {
+            match merged.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, total)) => *total += score,
+                None => merged.push((label, score)),
+            }
+        }
+        merged.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let (label, confidence) = merged.first().cloned()?;
+        let total: f32 = merged.iter().map(|(_, score)| score).sum();
+        let polarity = if total > 0.0 {
+            merged
+                .iter()
+                .map(|(label, score)| label.polarity() * score)
+                .sum::<f32>()
+                / total
+        } else {
+            label.polarity()
+        };
+
+        Some(SentimentScore {
+            label,
+            confidence,
+            distribution: merged,
+            polarity,
+        })
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum SentimentLabel {
     VeryPositive,
     Positive,
@@ -111,20 +435,50 @@ pub enum SentimentLabel {
     VeryNegative,
 }
 
-impl From<&str> for SentimentLabel {
-    fn from(label: &str) -> Self {
-        match label {
-            "Very Positive" => SentimentLabel::VeryPositive,
-            "Positive" => SentimentLabel::Positive,
-            "Neutral" => SentimentLabel::Neutral,
-            "Negative" => SentimentLabel::Negative,
-            "Very Negative" => SentimentLabel::VeryNegative,
-            _ => SentimentLabel::Neutral, // Default to Neutral if unknown
+impl TryFrom<&str> for SentimentLabel {
+    type Error = ProcessingError;
+
+    /// Parses a sentiment label, ignoring case and separators ("Very Negative", "very_negative",
+    /// "VERY-NEGATIVE") and accepting star ratings ("1 star" to "5 stars").
+    fn try_from(label: &str) -> Result<Self, Self::Error> {
+        match normalize_label(label).as_str() {
+            "very positive" | "5 star" | "5 stars" => Ok(SentimentLabel::VeryPositive),
+            "positive" | "4 star" | "4 stars" => Ok(SentimentLabel::Positive),
+            "neutral" | "3 star" | "3 stars" => Ok(SentimentLabel::Neutral),
+            "negative" | "2 star" | "2 stars" => Ok(SentimentLabel::Negative),
+            "very negative" | "1 star" | "1 stars" => Ok(SentimentLabel::VeryNegative),
+            _ => Err(ProcessingError::SentimentAnalysis(format!(
+                "Unknown sentiment label: {}",
+                label
+            ))),
+        }
+    }
+}
+
+impl SentimentLabel {
+    /// Position of the label on a scale from -1.0 (very negative) to 1.0 (very positive)
+    pub fn polarity(&self) -> f32 {
+        match self {
+            SentimentLabel::VeryPositive => 1.0,
+            SentimentLabel::Positive => 0.5,
+            SentimentLabel::Neutral => 0.0,
+            SentimentLabel::Negative => -0.5,
+            SentimentLabel::VeryNegative => -1.0,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+/// Lowercases a label and replaces `_` and `-` separators with single spaces
+pub(crate) fn normalize_label(label: &str) -> String {
+    label
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, JsonSchema)]
 pub enum TicketCategory {
     Billing,
     Account,
@@ -135,14 +489,210 @@ pub enum TicketCategory {
     Other,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+impl TicketCategory {
+    pub const ALL: [TicketCategory; 7] = [
+        TicketCategory::Billing,
+        TicketCategory::Account,
+        TicketCategory::General,
+        TicketCategory::Technical,
+        TicketCategory::Sales,
+        TicketCategory::Feedback,
+        TicketCategory::Other,
+    ];
+
+    /// Short description of the category, used in prompts
+    pub fn description(&self) -> &'static str {
+        match self {
+            TicketCategory::Billing => "payments, invoices, refunds, charges and subscriptions",
+            TicketCategory::Account => "login, passwords, profile and account access",
+            TicketCategory::General => "general questions about the product or company",
+            TicketCategory::Technical => "bugs, crashes, errors and outages",
+            TicketCategory::Sales => "pricing, plans, quotes and purchasing",
+            TicketCategory::Feedback => "suggestions, praise and complaints about the product",
+            TicketCategory::Other => "anything that fits none of the other categories",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TicketPriority {
+    /// Not worth an agent's time, e.g. spam or empty messages
+    Ignore,
     Low,
     Medium,
     High,
     Critical,
 }
 
+/// An escalation phrase found in a ticket, such as "production down" or "chargeback"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UrgencySignal {
+    /// What the phrase indicates, e.g. "outage" or "legal"
+    pub kind: String,
+    /// The configured phrase that matched
+    pub phrase: String,
+    pub effect: UrgencyEffect,
+}
+
+/// How an urgency signal changes the priority of a ticket
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum UrgencyEffect {
+    /// Multiplies the priority score
+    Boost(f32),
+    /// Raises the priority to at least this level, whatever the score
+    MinimumPriority(TicketPriority),
+}
+
+/// Whether an inbound message was written by a customer or is noise such as an autoresponder
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MessageKind {
+    Genuine,
+    OutOfOffice,
+    AutoReply,
+    Bounce,
+    Spam,
+    Empty,
+}
+
+impl MessageKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            MessageKind::Genuine => "genuine",
+            MessageKind::OutOfOffice => "out-of-office",
+            MessageKind::AutoReply => "automatic reply",
+            MessageKind::Bounce => "bounced",
+            MessageKind::Spam => "spam",
+            MessageKind::Empty => "empty",
+        }
+    }
+}
+
+/// Links a ticket to the first ticket of a cluster of (near-)duplicates, e.g. the reports of
+/// an outage
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DuplicateLink {
+    /// The earliest ticket of the cluster, which all its duplicates point to
+    pub parent_id: String,
+    /// Similarity to the most similar ticket of the cluster, from 0 to 1
+    pub similarity: f32,
+    /// Number of tickets in the cluster, including the parent and this ticket
+    pub cluster_size: usize,
+}
+
+/// Who handles the ticket
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Routing {
+    pub team: String,
+    pub queue: String,
+    /// The agent the ticket is assigned to, if the team assigns tickets to agents
+    pub agent: Option<String>,
+}
+
+/// What the language detector found in a ticket
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LanguageDetection {
+    /// `None` if the text is in no language the detector knows, e.g. only digits
+    pub detected: Option<Language>,
+    /// From 0 to 1
+    pub confidence: f64,
+    /// Whether the detector is confident enough for the language to be trusted
+    pub reliable: bool,
+    /// Whether the detected language is among the languages the detector is configured to
+    /// choose from. Text in none of them is detected among all languages.
+    pub supported: bool,
+    /// Writing system of the text, e.g. "Latin"
+    pub script: Option<String>,
+    /// Other languages the text could be in, most likely first
+    pub alternatives: Vec<Language>,
+    /// Where the language of the ticket comes from
+    pub source: LanguageSource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LanguageSource {
+    /// The detected language
+    Detected,
+    /// The customer's locale, as the text was too short or the detection unreliable
+    CustomerLocale,
+    /// The configured default language, as the text was too short or the detection unreliable
+    Default,
+}
+
+/// Which text of a ticket a processor reads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentSource {
+    /// The text the customer wrote
+    #[default]
+    Original,
+    /// The English translation, for processors with English-only rules
+    Translated,
+}
+
+/// English translation of a ticket. It is made from the redacted text, so personal data
+/// appears as placeholders such as `[EMAIL]`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Translation {
+    pub source_language: Language,
+    pub subject: Option<String>,
+    pub content: String,
+}
+
+/// A short version of the ticket for agents
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TicketSummary {
+    pub title: String,
+    pub bullets: Vec<String>,
+    /// Whether the summary consists of sentences picked from the ticket rather than being
+    /// written by an LLM
+    pub extractive: bool,
+}
+
+/// A reply for an agent to review and send, filled in from a reply template
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReplyDraft {
+    /// Name of the template the draft was made from
+    pub template: String,
+    pub text: String,
+    /// How well the template fits the ticket, from 0 to 1
+    pub score: f32,
+    /// Whether an LLM reworded the filled-in template
+    pub polished: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChurnRiskLevel {
+    None,
+    Low,
+    High,
+}
+
+/// How likely the customer is to leave, with the observations that led to it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChurnRisk {
+    pub level: ChurnRiskLevel,
+    /// Human readable reasons, e.g. "mentions competitor Zendesk"
+    pub evidence: Vec<String>,
+}
+
+/// Signals attached to a ticket that suggest a human should double-check the automated results
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReviewSignal {
+    /// The members of an ensemble processor did not agree on a field
+    EnsembleDisagreement {
+        field: String,
+        votes: Vec<String>,
+        agreement: f32, // Fraction of successful members that voted for the winning value
+    },
+    /// The ticket text looks like it is trying to steer the LLM; `matches` names the heuristics
+    PossiblePromptInjection { matches: Vec<String> },
+    /// The ticket text was shortened before a processor sent it to an external service
+    ContentTruncated {
+        processor: String,
+        original_chars: usize,
+        sent_chars: usize,
+    },
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -175,4 +725,82 @@ mod tests {
         assert_eq!(base.category, ProcessingResult::Processing);
         assert_eq!(base.priority, ProcessingResult::Processing);
     }
+
+    #[test]
+    fn test_processed_ticket_merge_review_signals() {
+        let ticket = SupportTicket::new(
+            "test-1".to_string(),
+            "Test ticket content".to_string(),
+            Utc::now(),
+            "customer1".to_string(),
+        );
+        let signal = ReviewSignal::EnsembleDisagreement {
+            field: "sentiment".to_string(),
+            votes: vec!["Negative".to_string(), "Neutral".to_string()],
+            agreement: 0.5,
+        };
+        let mut base = ProcessedTicket::new(ticket.clone()).with_review_signal(signal.clone());
+        let update = ProcessedTicket::new(ticket).with_review_signal(signal.clone());
+
+        base.merge_from(update);
+
+        assert_eq!(base.review_signals, vec![signal]);
+    }
+
+    #[test]
+    fn test_sentiment_score_from_distribution() {
+        let score = SentimentScore::from_distribution(vec![
+            (SentimentLabel::Negative, 0.5),
+            (SentimentLabel::VeryNegative, 0.25),
+            (SentimentLabel::Neutral, 0.125),
+            (SentimentLabel::Negative, 0.125),
+        ])
+        .unwrap();
+
+        assert_eq!(score.label, SentimentLabel::Negative);
+        assert_eq!(score.confidence, 0.625);
+        assert_eq!(
+            score.distribution,
+            vec![
+                (SentimentLabel::Negative, 0.625),
+                (SentimentLabel::VeryNegative, 0.25),
+                (SentimentLabel::Neutral, 0.125),
+            ]
+        );
+        assert_eq!(score.polarity, -0.5625);
+
+        assert_eq!(SentimentScore::from_distribution(Vec::new()), None);
+        assert_eq!(
+            SentimentScore::new(SentimentLabel::Positive, 0.9).polarity,
+            0.5
+        );
+    }
+
+    #[test]
+    fn test_sentiment_label_try_from() {
+        assert_eq!(
+            SentimentLabel::try_from("Very Negative"),
+            Ok(SentimentLabel::VeryNegative)
+        );
+        assert_eq!(
+            SentimentLabel::try_from("very_negative"),
+            Ok(SentimentLabel::VeryNegative)
+        );
+        assert_eq!(
+            SentimentLabel::try_from("POSITIVE"),
+            Ok(SentimentLabel::Positive)
+        );
+        assert_eq!(
+            SentimentLabel::try_from("1 star"),
+            Ok(SentimentLabel::VeryNegative)
+        );
+        assert_eq!(
+            SentimentLabel::try_from("4 stars"),
+            Ok(SentimentLabel::Positive)
+        );
+        assert!(matches!(
+            SentimentLabel::try_from("LABEL_7"),
+            Err(ProcessingError::SentimentAnalysis(_))
+        ));
+    }
 }
@@ -1,5 +1,6 @@
 use std::{collections::HashMap, sync::Arc};
 
+use chrono::{DateTime, Utc};
 use tokio::sync::RwLock;
 
 use crate::ticket::ProcessedTicket;
@@ -33,6 +34,48 @@ impl TicketStore {
         self.tickets.read().await.get(id).cloned()
     }
 
+    /// Returns the customer's tickets, oldest first
+    pub async fn tickets_for_customer(&self, customer_id: &str) -> Vec<ProcessedTicket> {
+        let mut tickets: Vec<ProcessedTicket> = self
+            .tickets
+            .read()
+            .await
+            .values()
+            .filter(|ticket| ticket.ticket.customer_id == customer_id)
+            .cloned()
+            .collect();
+        tickets.sort_by_key(|ticket| ticket.ticket.timestamp);
+        tickets
+    }
+
+    pub async fn tickets(&self) -> Vec<ProcessedTicket> {
+        self.tickets.read().await.values().cloned().collect()
+    }
+
+    /// Returns the tickets created at or after `since`, oldest first
+    pub async fn tickets_since(&self, since: DateTime<Utc>) -> Vec<ProcessedTicket> {
+        let mut tickets: Vec<ProcessedTicket> = self
+            .tickets
+            .read()
+            .await
+            .values()
+            .filter(|ticket| ticket.ticket.timestamp >= since)
+            .cloned()
+            .collect();
+        tickets.sort_by_key(|ticket| ticket.ticket.timestamp);
+        tickets
+    }
+
+    /// Calls `visit` with each ticket created at or after `since`, without copying the tickets
+    pub async fn visit_since(&self, since: DateTime<Utc>, visit: impl FnMut(&ProcessedTicket)) {
+        self.tickets
+            .read()
+            .await
+            .values()
+            .filter(|ticket| ticket.ticket.timestamp >= since)
+            .for_each(visit);
+    }
+
     pub async fn remove_ticket(&self, id: &str) {
         self.tickets.write().await.remove(id);
     }
@@ -0,0 +1,249 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::ticket::ReviewSignal;
+
+/// Rough number of characters per token, used to turn token limits into character limits
+/// without running a tokenizer
+pub const CHARS_PER_TOKEN: usize = 4;
+
+/// Share of the character budget kept from the beginning of the text, the rest is kept from
+/// the end
+const HEAD_SHARE: f32 = 0.7;
+
+const OMISSION_MARKER: &str = "\n[...]\n";
+
+/// Lines that start a quoted earlier message in a reply, e.g. "On Mon, 1 Jan 2024, Ann wrote:"
+static REPLY_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?im)^\s*(On\s.{1,200}\swrote:|Am\s.{1,200}\sschrieb\s.{1,200}:|El\s.{1,200}\sescribió:|Le\s.{1,200}\sa écrit\s?:|-{2,}\s*(Original Message|Forwarded message|Ursprüngliche Nachricht|Mensaje original|Message d'origine)\s*-{2,}|_{10,})\s*$",
+    )
+    .unwrap()
+});
+
+/// Lines that start an email signature
+static SIGNATURE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?im)^(--\s?|Sent from my .*|Get Outlook for .*|Von meinem .* gesendet\.?|Enviado desde mi .*)$")
+        .unwrap()
+});
+
+/// Limits applied to ticket text before it is sent to an external service.
+///
+/// Quoted reply chains and signatures are removed first. If the text is still over the limit,
+/// the beginning and the end are kept and the middle is replaced by a `[...]` marker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruncationConfig {
+    pub max_chars: Option<usize>,
+    /// Estimated with `CHARS_PER_TOKEN` characters per token
+    pub max_tokens: Option<usize>,
+    pub strip_quoted_replies: bool,
+    pub strip_signatures: bool,
+}
+
+impl Default for TruncationConfig {
+    fn default() -> Self {
+        Self {
+            max_chars: None,
+            max_tokens: None,
+            strip_quoted_replies: true,
+            strip_signatures: true,
+        }
+    }
+}
+
+/// Text after truncation, with the length of the original for reporting
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruncatedText {
+    pub text: String,
+    pub original_chars: usize,
+}
+
+impl TruncatedText {
+    pub fn is_truncated(&self) -> bool {
+        self.text.chars().count() < self.original_chars
+    }
+
+    /// The signal recording the truncation, if the text was shortened
+    pub fn review_signal(&self, processor: &str) -> Option<ReviewSignal> {
+        self.is_truncated().then(|| ReviewSignal::ContentTruncated {
+            processor: processor.to_string(),
+            original_chars: self.original_chars,
+            sent_chars: self.text.chars().count(),
+        })
+    }
+}
+
+impl TruncationConfig {
+    /// Sends the text unchanged
+    pub fn disabled() -> Self {
+        Self {
+            strip_quoted_replies: false,
+            strip_signatures: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_chars(mut self, max_chars: usize) -> Self {
+        self.max_chars = Some(max_chars);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_quoted_replies(mut self, strip: bool) -> Self {
+        self.strip_quoted_replies = strip;
+        self
+    }
+
+    pub fn with_signatures(mut self, strip: bool) -> Self {
+        self.strip_signatures = strip;
+        self
+    }
+
+    /// The effective character limit, the smaller of `max_chars` and the token estimate
+    pub fn char_limit(&self) -> Option<usize> {
+        let token_chars = self.max_tokens.map(|tokens| tokens * CHARS_PER_TOKEN);
+        match (self.max_chars, token_chars) {
+            (Some(chars), Some(token_chars)) => Some(chars.min(token_chars)),
+            (chars, token_chars) => chars.or(token_chars),
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> TruncatedText {
+        let original_chars = text.chars().count();
+        let mut result = text.to_string();
+        if self.strip_quoted_replies {
+            result = strip_quoted_replies(&result);
+        }
+        if self.strip_signatures {
+            result = cut_at_first(&SIGNATURE, &result);
+        }
+        if let Some(limit) = self.char_limit() {
+            result = keep_head_and_tail(&result, limit);
+        }
+
+        TruncatedText {
+            text: result,
+            original_chars,
+        }
+    }
+}
+
+fn strip_quoted_replies(text: &str) -> String {
+    let text = cut_at_first(&REPLY_HEADER, text);
+    let unquoted = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('>'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    // A ticket that is nothing but a quote is kept as it is
+    if unquoted.trim().is_empty() {
+        text
+    } else {
+        unquoted
+    }
+}
+
+/// Cuts the text at the first line matching the pattern, unless nothing would be left
+fn cut_at_first(pattern: &Regex, text: &str) -> String {
+    match pattern.find(text) {
+        Some(found) if !text[..found.start()].trim().is_empty() => {
+            text[..found.start()].trim_end().to_string()
+        }
+        _ => text.to_string(),
+    }
+}
+
+fn keep_head_and_tail(text: &str, limit: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= limit {
+        return text.to_string();
+    }
+    let marker_chars = OMISSION_MARKER.chars().count();
+    if limit <= marker_chars * 2 {
+        return chars[..limit].iter().collect();
+    }
+
+    let budget = limit - marker_chars;
+    let head = (budget as f32 * HEAD_SHARE) as usize;
+    let tail = budget - head;
+    let mut result: String = chars[..head].iter().collect();
+    result.push_str(OMISSION_MARKER);
+    result.extend(&chars[chars.len() - tail..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_quoted_replies_and_signatures() {
+        let text = "The export still fails.\n\n--\nAnn Smith\nAcme Inc\n\nOn Mon, 3 Mar 2025 at 10:00, Support <help@example.com> wrote:\n> Please try again.\n> Thanks";
+        let truncated = TruncationConfig::default().apply(text);
+
+        assert_eq!(truncated.text, "The export still fails.");
+        assert!(truncated.is_truncated());
+
+        let inline = "See below\n> quoted line\nmy answer\nSent from my iPhone";
+        assert_eq!(
+            TruncationConfig::default().apply(inline).text,
+            "See below\nmy answer"
+        );
+
+        // A message that is nothing but a quote is left alone
+        let only_quote = "> forwarded text";
+        assert_eq!(
+            TruncationConfig::default().apply(only_quote).text,
+            only_quote
+        );
+        assert_eq!(
+            TruncationConfig::disabled().apply(text).text,
+            text.to_string()
+        );
+    }
+
+    #[test]
+    fn test_keeps_head_and_tail() {
+        let text = format!("{}{}{}", "a".repeat(100), "b".repeat(1000), "c".repeat(100));
+        let truncated = TruncationConfig::default().with_max_chars(107).apply(&text);
+
+        assert_eq!(
+            truncated.text,
+            format!("{}{}{}", "a".repeat(70), OMISSION_MARKER, "c".repeat(30))
+        );
+        assert_eq!(truncated.text.chars().count(), 107);
+        assert_eq!(truncated.original_chars, 1200);
+
+        // Short texts are sent unchanged
+        let short = TruncationConfig::default().with_max_chars(107).apply("hi");
+        assert_eq!(short.text, "hi");
+        assert!(!short.is_truncated());
+    }
+
+    #[test]
+    fn test_char_limit() {
+        assert_eq!(TruncationConfig::default().char_limit(), None);
+        assert_eq!(
+            TruncationConfig::default()
+                .with_max_tokens(100)
+                .char_limit(),
+            Some(400)
+        );
+        assert_eq!(
+            TruncationConfig::default()
+                .with_max_tokens(100)
+                .with_max_chars(300)
+                .char_limit(),
+            Some(300)
+        );
+
+        let text = "ü".repeat(50);
+        let truncated = TruncationConfig::default().with_max_tokens(5).apply(&text);
+        assert_eq!(truncated.text.chars().count(), 20);
+    }
+}